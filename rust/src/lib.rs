@@ -0,0 +1,4338 @@
+//! Platform-independent core of BadderBlood: the rain simulation, payload
+//! menu, options and the cell-buffer / VT renderer. `main.rs` wires it to
+//! the Win32 console.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+// ---------------------------------------------------------------------------
+// Fast xoshiro256++ PRNG – vastly faster than rand::thread_rng()
+// ---------------------------------------------------------------------------
+
+struct Rng {
+    s: [u64; 4],
+}
+
+impl Rng {
+    fn new() -> Self {
+        let t = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let mut s = [
+            t ^ 0x9E3779B97F4A7C15,
+            t.wrapping_mul(0x6C62272E07BB0142) ^ 0xBF58476D1CE4E5B9,
+            t.wrapping_mul(0x94D049BB133111EB) ^ 0x853C49E6748FEA9B,
+            t.rotate_left(17) ^ 0x2545F4914F6CDD1D,
+        ];
+        for _ in 0..8 {
+            let t = s[1] << 17;
+            s[2] ^= s[0];
+            s[3] ^= s[1];
+            s[1] ^= s[2];
+            s[0] ^= s[3];
+            s[2] ^= t;
+            s[3] = s[3].rotate_left(45);
+        }
+        Self { s }
+    }
+
+    /// Deterministic state from a 64-bit seed (splitmix64 expansion), so
+    /// equal seeds replay the same rain.
+    fn from_seed(seed: u64) -> Self {
+        let mut x = seed;
+        let mut splitmix = || {
+            x = x.wrapping_add(0x9E3779B97F4A7C15);
+            let mut z = x;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+            z ^ (z >> 31)
+        };
+        Self {
+            s: [splitmix(), splitmix(), splitmix(), splitmix()],
+        }
+    }
+
+    #[inline(always)]
+    fn next_u64(&mut self) -> u64 {
+        let result = (self.s[0].wrapping_add(self.s[3]))
+            .rotate_left(23)
+            .wrapping_add(self.s[0]);
+        let t = self.s[1] << 17;
+        self.s[2] ^= self.s[0];
+        self.s[3] ^= self.s[1];
+        self.s[1] ^= self.s[2];
+        self.s[0] ^= self.s[3];
+        self.s[2] ^= t;
+        self.s[3] = self.s[3].rotate_left(45);
+        result
+    }
+
+    #[inline(always)]
+    fn gen_u32(&mut self, n: u32) -> u32 {
+        let r = self.next_u64() as u32;
+        ((r as u64).wrapping_mul(n as u64) >> 32) as u32
+    }
+
+    #[inline(always)]
+    fn gen_range(&mut self, lo: i32, hi_exclusive: i32) -> i32 {
+        let span = (hi_exclusive - lo) as u32;
+        lo + self.gen_u32(span) as i32
+    }
+
+    #[inline(always)]
+    fn gen_range_u16(&mut self, lo: u16, hi_inclusive: u16) -> u16 {
+        let span = (hi_inclusive - lo + 1) as u32;
+        lo + self.gen_u32(span) as u16
+    }
+
+    #[inline(always)]
+    fn gen_bool(&mut self, p_numer: u32, p_denom: u32) -> bool {
+        self.gen_u32(p_denom) < p_numer
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Character set – ASCII chars that evoke the Matrix aesthetic
+// ---------------------------------------------------------------------------
+
+const MATRIX_CHARS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz+-*=<>:;|~!@#$%^&(){}[]/?\\";
+
+/// `--glyphset binary` and `--glyphset hex`.
+const BINARY_CHARS: &[u8] = b"01";
+const HEX_CHARS: &[u8] = b"0123456789ABCDEF";
+/// `--glyphset ascii`: every printable character except the space.
+const ASCII_CHARS: std::ops::RangeInclusive<u8> = b'!'..=b'~';
+
+/// Named built-in glyph pools, picked with `--glyphset`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum GlyphPreset {
+    /// Letters, digits and symbols (`MATRIX_CHARS`), the default.
+    Matrix,
+    Binary,
+    Hex,
+    Ascii,
+}
+
+impl GlyphPreset {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "matrix" => Some(GlyphPreset::Matrix),
+            "binary" => Some(GlyphPreset::Binary),
+            "hex" => Some(GlyphPreset::Hex),
+            "ascii" => Some(GlyphPreset::Ascii),
+            _ => None,
+        }
+    }
+
+    fn chars(self) -> Vec<u8> {
+        match self {
+            GlyphPreset::Matrix => MATRIX_CHARS.to_vec(),
+            GlyphPreset::Binary => BINARY_CHARS.to_vec(),
+            GlyphPreset::Hex => HEX_CHARS.to_vec(),
+            GlyphPreset::Ascii => ASCII_CHARS.collect(),
+        }
+    }
+}
+
+/// A rain glyph as UTF-16: one unit, or a surrogate pair for characters
+/// beyond the BMP (emoji), which is drawn across two cells.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Glyph {
+    unit: u16,
+    /// Low surrogate following `unit` (then a high surrogate).
+    low: Option<u16>,
+}
+
+impl Glyph {
+    const SPACE: Glyph = Glyph::unit(b' ' as u16);
+
+    const fn unit(unit: u16) -> Self {
+        Self { unit, low: None }
+    }
+
+    fn from_char(ch: char) -> Self {
+        let mut units = [0u16; 2];
+        match *ch.encode_utf16(&mut units) {
+            [unit] => Self::unit(unit),
+            [high, low] => Self { unit: high, low: Some(low) },
+            _ => Self::SPACE,
+        }
+    }
+
+    /// Console cells the glyph occupies.
+    fn width(self) -> u8 {
+        if self.low.is_some() {
+            2
+        } else {
+            char_width(self.unit)
+        }
+    }
+}
+
+/// Pool of glyphs the rain draws from, optionally weighted.
+pub struct GlyphSet {
+    glyphs: Vec<Glyph>,
+    /// Alias table (accept threshold out of 2^32, alias index) for weighted
+    /// O(1) sampling; empty when every glyph is equally likely.
+    alias: Vec<(u64, u32)>,
+}
+
+impl GlyphSet {
+    pub fn builtin() -> Self {
+        Self::preset(GlyphPreset::Matrix)
+    }
+
+    /// One of the named built-in pools, every glyph equally likely.
+    pub fn preset(preset: GlyphPreset) -> Self {
+        Self {
+            glyphs: preset.chars().iter().map(|&b| Glyph::unit(b as u16)).collect(),
+            alias: Vec::new(),
+        }
+    }
+
+    /// Load a UTF-8 glyph file. Every character on a line is a glyph of weight
+    /// 1, except that a line of the form `<glyph> <weight>` (e.g. `カ 5`)
+    /// gives that glyph an explicit weight. Repeats add up.
+    pub fn load(path: &std::path::Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut glyphs: Vec<Glyph> = Vec::new();
+        let mut weights: Vec<u32> = Vec::new();
+        let mut add = |ch: char, weight: u32| {
+            let glyph = Glyph::from_char(ch);
+            match glyphs.iter().position(|&g| g == glyph) {
+                Some(i) => weights[i] = weights[i].saturating_add(weight),
+                None => {
+                    glyphs.push(glyph);
+                    weights.push(weight);
+                }
+            }
+        };
+
+        for line in text.lines() {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            if let [glyph, weight] = tokens[..] {
+                let mut chars = glyph.chars();
+                if let (Some(ch), None, Ok(w)) = (chars.next(), chars.next(), weight.parse()) {
+                    add(ch, w);
+                    continue;
+                }
+            }
+            for ch in tokens.iter().flat_map(|t| t.chars()) {
+                add(ch, 1);
+            }
+        }
+
+        let (glyphs, weights): (Vec<Glyph>, Vec<u32>) = glyphs
+            .into_iter()
+            .zip(weights)
+            .filter(|&(_, w)| w > 0)
+            .unzip();
+        if glyphs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no usable glyphs",
+            ));
+        }
+        let alias = if weights.iter().all(|&w| w == weights[0]) {
+            Vec::new()
+        } else {
+            build_alias_table(&weights)
+        };
+        Ok(Self { glyphs, alias })
+    }
+
+    #[inline(always)]
+    fn sample(&self, rng: &mut Rng) -> Glyph {
+        let i = rng.gen_u32(self.glyphs.len() as u32) as usize;
+        if self.alias.is_empty() {
+            return self.glyphs[i];
+        }
+        let (threshold, alias) = self.alias[i];
+        if ((rng.next_u64() as u32) as u64) < threshold {
+            self.glyphs[i]
+        } else {
+            self.glyphs[alias as usize]
+        }
+    }
+}
+
+/// Vose's alias method: each slot keeps itself with probability
+/// `threshold / 2^32` and otherwise yields its alias.
+fn build_alias_table(weights: &[u32]) -> Vec<(u64, u32)> {
+    const ONE: u64 = 1 << 32;
+    let n = weights.len() as u128;
+    let total: u128 = weights.iter().map(|&w| w as u128).sum();
+    let mut scaled: Vec<u64> = weights
+        .iter()
+        .map(|&w| (((w as u128 * n) << 32) / total) as u64)
+        .collect();
+
+    let mut table: Vec<(u64, u32)> = (0..weights.len()).map(|i| (ONE, i as u32)).collect();
+    let (mut small, mut large): (Vec<usize>, Vec<usize>) =
+        (0..weights.len()).partition(|&i| scaled[i] < ONE);
+    while let (Some(s), Some(&l)) = (small.pop(), large.last()) {
+        table[s] = (scaled[s], l as u32);
+        scaled[l] -= ONE - scaled[s];
+        if scaled[l] < ONE {
+            large.pop();
+            small.push(l);
+        }
+    }
+    // Anything left over is 1.0 up to rounding and keeps itself.
+    table
+}
+
+/// Half-width katakana block (U+FF66..U+FF9D) mixed into the rain.
+const KATAKANA_U16: std::ops::RangeInclusive<u16> = 0xFF66..=0xFF9D;
+
+/// Pick a rain glyph: half-width katakana with probability
+/// `ratio.0 / ratio.1`, otherwise one from `glyphs`.
+#[inline(always)]
+fn random_glyph(glyphs: &GlyphSet, ratio: (u32, u32), rng: &mut Rng) -> Glyph {
+    if ratio.0 > 0 && rng.gen_bool(ratio.0, ratio.1) {
+        Glyph::unit(rng.gen_range_u16(*KATAKANA_U16.start(), *KATAKANA_U16.end()))
+    } else {
+        glyphs.sample(rng)
+    }
+}
+
+/// Number of console cells a code unit occupies: 2 for East Asian wide and
+/// fullwidth forms (which a custom glyph set may contain), 1 otherwise.
+fn char_width(ch: u16) -> u8 {
+    match ch {
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6 => 2,
+        _ => 1,
+    }
+}
+
+fn is_high_surrogate(unit: u16) -> bool {
+    (0xD800..=0xDBFF).contains(&unit)
+}
+
+fn is_low_surrogate(unit: u16) -> bool {
+    (0xDC00..=0xDFFF).contains(&unit)
+}
+
+// ---------------------------------------------------------------------------
+// Console colour attributes (Win32 4-bit) -> ANSI SGR mapping
+// ---------------------------------------------------------------------------
+
+const ATTR_BLACK: u16 = 0x0000;
+const ATTR_HEAD: u16 = 0x0F;    // bright white
+const ATTR_ERROR: u16 = 0x04;   // red (error text)
+
+/// Flag marking an attribute as an xterm 256-colour foreground index
+/// (low byte) on black rather than a Win32 4-bit attribute.
+const ATTR_INDEXED: u16 = 0x1000;
+
+/// Flag marking a 24-bit colour: the theme hue (bits 9-10, `ColorTheme`
+/// order) at brightness `level` (low byte), blended toward white when
+/// `ATTR_RGB_WHITE` is set. Self-describing so `write_sgr` stays stateless.
+const ATTR_RGB: u16 = 0x2000;
+const ATTR_RGB_WHITE: u16 = 0x0100;
+/// With `ATTR_RGB`: bits 9-11 index `RAINBOW_RGB` instead of a theme.
+const ATTR_RGB_HUE: u16 = 0x4000;
+/// On top of any colour: also SGR bold (`--bold-head`). Colour sequences
+/// leave bold alone, so `diff_cells` turns it off for the next cell.
+const ATTR_BOLD: u16 = 0x8000;
+
+/// `--rainbow` band colours, 24-bit and as bright 4-bit attributes.
+const RAINBOW_RGB: [[u8; 3]; 8] = [
+    [255, 40, 40],
+    [255, 140, 0],
+    [255, 230, 0],
+    [0, 255, 70],
+    [0, 230, 230],
+    [60, 140, 255],
+    [130, 70, 255],
+    [255, 60, 200],
+];
+const RAINBOW_ATTRS: [u16; 6] = [0x0C, 0x0E, 0x0A, 0x0B, 0x09, 0x0D];
+
+/// Columns per rainbow band, and frames per one-band shift.
+const RAINBOW_BAND_COLS: usize = 4;
+const RAINBOW_SCROLL_FRAMES: u64 = 6;
+
+fn rgb_attr(theme: ColorTheme, level: u8, white: bool) -> u16 {
+    let white = if white { ATTR_RGB_WHITE } else { 0 };
+    ATTR_RGB | (theme as u16) << 9 | white | level as u16
+}
+
+const TRAIL_PALETTE_SIZE: usize = 16;
+
+/// How colours are emitted to the terminal.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ColorMode {
+    /// Win32-style 4-bit attributes mapped to 16-colour SGR codes.
+    Ansi16,
+    /// xterm 256-colour palette indices (`38;5;N`).
+    Palette256,
+    /// 24-bit `38;2;R;G;B` with a smooth fade down the trail.
+    TrueColor,
+}
+
+impl ColorMode {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "4bit" => Some(ColorMode::Ansi16),
+            "256" => Some(ColorMode::Palette256),
+            "truecolor" | "24bit" => Some(ColorMode::TrueColor),
+            _ => None,
+        }
+    }
+}
+
+/// Hue of the rain and the UI accents.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ColorTheme {
+    Green,
+    Red,
+    Amber,
+    Blue,
+}
+
+impl ColorTheme {
+    const ALL: [ColorTheme; 4] = [
+        ColorTheme::Green,
+        ColorTheme::Red,
+        ColorTheme::Amber,
+        ColorTheme::Blue,
+    ];
+
+    fn parse(s: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|t| t.name().eq_ignore_ascii_case(s))
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ColorTheme::Green => "green",
+            ColorTheme::Red => "red",
+            ColorTheme::Amber => "amber",
+            ColorTheme::Blue => "blue",
+        }
+    }
+
+    /// (bright, dark) Win32 foreground attributes.
+    fn attrs(self) -> (u16, u16) {
+        match self {
+            ColorTheme::Green => (0x0A, 0x02),
+            ColorTheme::Red => (0x0C, 0x04),
+            ColorTheme::Amber => (0x0E, 0x06),
+            ColorTheme::Blue => (0x09, 0x01),
+        }
+    }
+
+    /// 4-bit colour of the cell right behind the white head, a paler or
+    /// warmer neighbour of the bright colour so the head fades in steps.
+    fn accent(self) -> u16 {
+        match self {
+            ColorTheme::Green => 0x0B,
+            ColorTheme::Red => 0x0D,
+            ColorTheme::Amber => 0x0C,
+            ColorTheme::Blue => 0x0B,
+        }
+    }
+
+    /// Full-brightness 24-bit colour.
+    fn rgb(self) -> [u8; 3] {
+        match self {
+            ColorTheme::Green => [0, 255, 70],
+            ColorTheme::Red => [255, 40, 40],
+            ColorTheme::Amber => [255, 176, 0],
+            ColorTheme::Blue => [60, 140, 255],
+        }
+    }
+
+    /// 256-colour cube shades from brightest to darkest.
+    fn ramp_256(self) -> [u8; 5] {
+        match self {
+            ColorTheme::Green => [46, 40, 34, 28, 22],
+            ColorTheme::Red => [196, 160, 124, 88, 52],
+            ColorTheme::Amber => [214, 172, 130, 94, 58],
+            ColorTheme::Blue => [39, 32, 25, 18, 17],
+        }
+    }
+}
+
+/// How quickly a trail darkens from just behind the head to its tail.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum DecayCurve {
+    /// Even steps all the way down.
+    Linear,
+    /// Drops off sharply behind the head, then levels out near the tail.
+    Exp,
+    /// Four flat bands.
+    Stepped,
+}
+
+impl DecayCurve {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "linear" => Some(DecayCurve::Linear),
+            "exp" => Some(DecayCurve::Exp),
+            "stepped" => Some(DecayCurve::Stepped),
+            _ => None,
+        }
+    }
+
+    /// Remap trail position `pos` of `span` to how far along the fade it
+    /// should look; `Linear` leaves it unchanged.
+    fn apply(self, pos: usize, span: usize) -> usize {
+        let pos = pos.min(span);
+        match self {
+            DecayCurve::Linear => pos,
+            DecayCurve::Exp => {
+                const K: f32 = 4.0;
+                let t = pos as f32 / span.max(1) as f32;
+                let f = (1.0 - (-K * t).exp()) / (1.0 - (-K).exp());
+                (f * span as f32).round() as usize
+            }
+            DecayCurve::Stepped => {
+                const BANDS: usize = 4;
+                (pos * BANDS / (span + 1)) * span / (BANDS - 1)
+            }
+        }
+    }
+}
+
+struct AttrPalette {
+    head: u16,
+    near_head: [u16; 2],
+    trail: [u16; TRAIL_PALETTE_SIZE],
+    /// Theme's bright 4-bit colour: menu frame, titles, messages.
+    bright: u16,
+    /// Theme's dark 4-bit colour: status bar, secondary menu text.
+    dim: u16,
+    /// Black on the theme colour: menu selection.
+    selected: u16,
+    /// Truecolor: trail shades are computed per row instead of from `trail`.
+    smooth: Option<ColorTheme>,
+    /// Fade shape for `smooth` trails (already baked into `trail`).
+    decay: DecayCurve,
+}
+
+/// Truecolor trail brightness just behind the near-head cells and at the
+/// far end of the longest trail.
+const RGB_TRAIL_START: u16 = 210;
+const RGB_TRAIL_END: u16 = 40;
+
+fn build_attr_palette(theme: ColorTheme, mode: ColorMode, decay: DecayCurve) -> AttrPalette {
+    let (bright, dim) = theme.attrs();
+    let mut trail = [0u16; TRAIL_PALETTE_SIZE];
+    let fade = |i: usize| decay.apply(i, TRAIL_PALETTE_SIZE - 1);
+    let (head, near_head) = match mode {
+        ColorMode::Ansi16 => {
+            let bright_end = TRAIL_PALETTE_SIZE * 6 / 10;
+            for (i, attr) in trail.iter_mut().enumerate() {
+                *attr = if fade(i) < bright_end { bright } else { dim };
+            }
+            (ATTR_HEAD, [theme.accent(), bright])
+        }
+        ColorMode::Palette256 => {
+            // Trail fades through the ramp below the near-head shade.
+            let ramp = theme.ramp_256();
+            let tail = &ramp[1..];
+            for (i, attr) in trail.iter_mut().enumerate() {
+                *attr = ATTR_INDEXED | tail[fade(i) * tail.len() / TRAIL_PALETTE_SIZE] as u16;
+            }
+            (
+                ATTR_INDEXED | 231,
+                [ATTR_INDEXED | ramp[0] as u16, ATTR_INDEXED | ramp[1] as u16],
+            )
+        }
+        ColorMode::TrueColor => {
+            for (i, attr) in trail.iter_mut().enumerate() {
+                *attr = rgb_trail_attr(theme, fade(i), TRAIL_PALETTE_SIZE - 1);
+            }
+            (
+                rgb_attr(theme, 255, true),
+                [rgb_attr(theme, 255, false), rgb_attr(theme, 232, false)],
+            )
+        }
+    };
+    AttrPalette {
+        head,
+        near_head,
+        trail,
+        bright,
+        dim,
+        selected: dim << 4,
+        smooth: (mode == ColorMode::TrueColor).then_some(theme),
+        decay,
+    }
+}
+
+/// Truecolor shade `pos` steps into a trail of `span` steps.
+fn rgb_trail_attr(theme: ColorTheme, pos: usize, span: usize) -> u16 {
+    let fade = (RGB_TRAIL_START - RGB_TRAIL_END) as usize * pos.min(span) / span.max(1);
+    rgb_attr(theme, (RGB_TRAIL_START - fade as u16) as u8, false)
+}
+
+/// Bright 4-bit colours menu categories cycle through, starting with the
+/// theme's own.
+const CATEGORY_ATTRS: [u16; 6] = [0x0A, 0x0B, 0x0E, 0x0D, 0x09, 0x0C];
+
+impl AttrPalette {
+    /// Colour of unselected menu category `ci`.
+    fn category_attr(&self, ci: usize) -> u16 {
+        match ci % CATEGORY_ATTRS.len() {
+            0 => self.bright,
+            n => CATEGORY_ATTRS
+                .iter()
+                .copied()
+                .filter(|&a| a != self.bright)
+                .nth(n - 1)
+                .unwrap_or(self.bright),
+        }
+    }
+
+    /// Near-head colour `pos` cells behind the head, in a bright zone of
+    /// `span` such cells; the palette's shades are stretched across it.
+    fn near_head_attr(&self, pos: usize, span: usize) -> u16 {
+        let idx = pos * self.near_head.len() / span.max(1);
+        self.near_head[idx.min(self.near_head.len() - 1)]
+    }
+
+    /// Trail colour `pos` cells past the near-head, for a drop whose trail
+    /// can run `span` cells.
+    fn trail_attr(&self, pos: usize, span: usize) -> u16 {
+        if let Some(theme) = self.smooth {
+            return rgb_trail_attr(theme, self.decay.apply(pos, span), span);
+        }
+        let idx = (pos * TRAIL_PALETTE_SIZE) / span.max(1);
+        self.trail[idx.min(TRAIL_PALETTE_SIZE - 1)]
+    }
+
+    /// One brightness step down: white -> bright theme colour -> dark theme
+    /// colour. Indexed colours in the 6x6x6 cube lose one step per channel.
+    fn dim_attr(&self, attr: u16) -> u16 {
+        if attr & ATTR_RGB != 0 {
+            if attr & ATTR_RGB_WHITE != 0 {
+                return attr & !ATTR_RGB_WHITE;
+            }
+            return (attr & !0xFF) | ((attr & 0xFF) * 5 / 8);
+        }
+        if attr & ATTR_INDEXED != 0 {
+            let n = attr & 0xFF;
+            if !(16..232).contains(&n) {
+                return attr;
+            }
+            let (r, g, b) = ((n - 16) / 36, (n - 16) / 6 % 6, (n - 16) % 6);
+            let n = 16 + 36 * r.saturating_sub(1) + 6 * g.saturating_sub(1) + b.saturating_sub(1);
+            return ATTR_INDEXED | n;
+        }
+        if attr == ATTR_HEAD {
+            self.bright
+        } else if attr & 0xF8 == 0x08 {
+            attr & 0x07
+        } else {
+            attr
+        }
+    }
+
+    /// Recolour a rain attribute for rainbow band `band`: truecolor shades
+    /// keep their brightness under the band's hue, and the theme's 4-bit
+    /// colours become the band's bright/dark pair. White heads stay white.
+    fn rainbow_attr(&self, attr: u16, band: usize) -> u16 {
+        if attr & ATTR_RGB != 0 {
+            let hue = (band % RAINBOW_RGB.len()) as u16;
+            return (attr & !0x0E00) | ATTR_RGB_HUE | hue << 9;
+        }
+        let bright = RAINBOW_ATTRS[band % RAINBOW_ATTRS.len()];
+        if attr == self.bright || attr == self.near_head[0] {
+            bright
+        } else if attr == self.dim {
+            bright & 0x07
+        } else {
+            attr
+        }
+    }
+
+    /// Darken `attr` for a drop of the given brightness (255 = unchanged),
+    /// one `dim_attr` step per quarter of lost intensity.
+    fn scale_attr(&self, attr: u16, brightness: u8) -> u16 {
+        let steps = (255 - brightness as u32) * 4 / 255;
+        (0..steps).fold(attr, |a, _| self.dim_attr(a))
+    }
+}
+
+/// Win32 colour bits (blue=1, green=2, red=4) -> ANSI colour number.
+const ANSI_FROM_WIN32: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+
+/// Append the ANSI SGR sequence for a Win32 4-bit console attribute
+/// (foreground in the low nibble, background in the high nibble). With
+/// `transparent` a black background is written as the default one.
+fn attr_to_sgr(buf: &mut Vec<u8>, attr: u16, transparent: bool) {
+    if attr & 0xFF == 0 {
+        buf.extend_from_slice(b"\x1b[0m"); // reset (black/default)
+        return;
+    }
+    let fg = (attr & 0x0F) as usize;
+    let bg = ((attr >> 4) & 0x0F) as usize;
+    let fg_base = if fg & 0x08 != 0 { 90 } else { 30 };
+    let bg_base = if bg & 0x08 != 0 { 100 } else { 40 };
+    buf.extend_from_slice(b"\x1b[");
+    write_usize(buf, fg_base + ANSI_FROM_WIN32[fg & 0x07] as usize);
+    buf.push(b';');
+    if bg == 0 && transparent {
+        buf.extend_from_slice(b"49");
+    } else {
+        write_usize(buf, bg_base + ANSI_FROM_WIN32[bg & 0x07] as usize);
+    }
+    buf.push(b'm');
+}
+
+/// Append the SGR sequence for `attr` (4-bit, 256-colour indexed or RGB).
+fn write_sgr(buf: &mut Vec<u8>, attr: u16, transparent: bool) {
+    // Indexed and RGB colours always sit on black (or the default)
+    let black_bg: &[u8] = if transparent { b";49m" } else { b";40m" };
+    if attr & ATTR_BOLD != 0 {
+        // Same sequence with `1;` spliced in after the CSI: `\x1b[1;97;40m`
+        let start = buf.len();
+        write_sgr(buf, attr & !ATTR_BOLD, transparent);
+        buf.splice(start + 2..start + 2, *b"1;");
+    } else if attr & ATTR_RGB != 0 {
+        let rgb = if attr & ATTR_RGB_HUE != 0 {
+            RAINBOW_RGB[((attr >> 9) & 0x07) as usize]
+        } else {
+            ColorTheme::ALL[((attr >> 9) & 0x03) as usize].rgb()
+        };
+        let level = (attr & 0xFF) as usize;
+        buf.extend_from_slice(b"\x1b[38;2;");
+        for (i, &c) in rgb.iter().enumerate() {
+            let mut c = c as usize;
+            if attr & ATTR_RGB_WHITE != 0 {
+                c += (255 - c) * 3 / 4;
+            }
+            if i > 0 {
+                buf.push(b';');
+            }
+            write_usize(buf, c * level / 255);
+        }
+        buf.extend_from_slice(black_bg);
+    } else if attr & ATTR_INDEXED != 0 {
+        buf.extend_from_slice(b"\x1b[38;5;");
+        write_usize(buf, (attr & 0xFF) as usize);
+        buf.extend_from_slice(black_bg);
+    } else {
+        attr_to_sgr(buf, attr, transparent);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Cell type for our logical framebuffer
+// ---------------------------------------------------------------------------
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    ch: u16,    // UTF-16 code unit
+    attr: u16,  // Win32 attribute value
+}
+
+impl Cell {
+    pub const BLANK: Cell = Cell { ch: b' ' as u16, attr: ATTR_BLACK };
+    /// Right half of a wide glyph in the cell to its left; never emitted.
+    const WIDE_TAIL: Cell = Cell { ch: 0, attr: ATTR_BLACK };
+    /// Matches no real cell; seeds the previous frame so everything repaints.
+    pub const STALE: Cell = Cell { ch: 0xFFFF, attr: 0xFFFF };
+}
+
+/// The characters of a `cols` x `rows` frame, one line per row, ignoring
+/// attributes. Lets tests compare rendered layouts as text.
+#[cfg(test)]
+fn cells_to_string(buf: &[Cell], cols: usize, rows: usize) -> String {
+    buf[..cols * rows]
+        .chunks(cols)
+        .map(|row| {
+            let units: Vec<u16> = row
+                .iter()
+                .filter(|&&cell| cell != Cell::WIDE_TAIL)
+                .map(|cell| cell.ch)
+                .collect();
+            String::from_utf16_lossy(&units)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// ---------------------------------------------------------------------------
+// A single falling "stream" – compact ring buffer
+// ---------------------------------------------------------------------------
+
+struct Drop {
+    col: u16,
+    head: i32,
+    /// Ring of the last `max_len` glyphs, sized to `max_len` on each reset
+    /// so trails can span any screen height.
+    chars: Vec<Glyph>,
+    len: u16,
+    write_pos: u16,
+    max_len: u16,
+    /// Frames per step; a streak steps every frame whatever this says, so
+    /// a `--fixed-column-speed` column keeps its speed across streaks.
+    speed: u8,
+    tick: u8,
+    glitch: bool,
+    /// Overall intensity of this drop, 255 = full palette brightness.
+    brightness: u8,
+    /// Fast "lightning" streak drawn entirely in the head colour.
+    streak: bool,
+}
+
+/// One drop in this many becomes a lightning streak.
+const STREAK_CHANCE: u32 = 50;
+
+/// Which way the rain runs. Drops always simulate as falling, `head`
+/// counting rows from where they enter; `Up` mirrors the rows when drawing,
+/// so rising streams enter from (and reset to) below the screen.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Down,
+    Up,
+}
+
+impl Direction {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "down" => Some(Direction::Down),
+            "up" => Some(Direction::Up),
+            _ => None,
+        }
+    }
+
+    /// Screen row of simulated row `r` on a screen `rows` tall.
+    fn screen_row(self, r: usize, rows: usize) -> usize {
+        match self {
+            Direction::Down => r,
+            Direction::Up => rows - 1 - r,
+        }
+    }
+}
+
+/// Random per-drop brightness for a jitter of `variation` percent.
+fn roll_brightness(variation: u8, rng: &mut Rng) -> u8 {
+    let spread = variation as u32 * 255 / 100;
+    (255 - rng.gen_u32(spread + 1)) as u8
+}
+
+impl Drop {
+    fn new(col: u16, rows: u16, config: &Config, rng: &mut Rng) -> Self {
+        let (lo, hi) = config.trail_range(rows);
+        let max_len = rng.gen_range_u16(lo, hi);
+        let speed = config.roll_speed(rng);
+        let head = -(rng.gen_range(0, rows as i32 + 10));
+        let mut drop = Self {
+            col,
+            head,
+            chars: Vec::new(),
+            len: 0,
+            write_pos: 0,
+            max_len,
+            speed,
+            tick: 0,
+            glitch: rng.gen_bool(config.glitch_chance, 1000),
+            brightness: roll_brightness(config.brightness_variation, rng),
+            streak: false,
+        };
+        drop.roll_streak(rows, config, rng);
+        drop.chars.resize(drop.max_len as usize, Glyph::SPACE);
+        drop
+    }
+
+    /// Occasionally make this a full-speed, full-length bright streak.
+    fn roll_streak(&mut self, rows: u16, config: &Config, rng: &mut Rng) {
+        self.streak = rng.gen_bool(1, STREAK_CHANCE);
+        if self.streak {
+            self.max_len = config.trail_range(rows).1;
+        }
+    }
+
+    fn reset(&mut self, rows: u16, config: &Config, rng: &mut Rng) {
+        self.head = -(rng.gen_range(0, rows as i32 / 2 + 5));
+        let (lo, hi) = config.trail_range(rows);
+        self.max_len = rng.gen_range_u16(lo, hi);
+        if !config.fixed_column_speed {
+            self.speed = config.roll_speed(rng);
+        }
+        self.len = 0;
+        self.write_pos = 0;
+        self.glitch = rng.gen_bool(config.glitch_chance, 1000);
+        self.brightness = roll_brightness(config.brightness_variation, rng);
+        self.roll_streak(rows, config, rng);
+        self.chars.resize(self.max_len as usize, Glyph::SPACE);
+    }
+
+    /// Advance one frame and move `wind` columns sideways. The drift
+    /// applies whether or not the drop steps, so slow and fast drops blow
+    /// the same way. Returns true on the step the head falls past the
+    /// bottom row.
+    #[inline]
+    fn update(
+        &mut self,
+        (cols, rows): (u16, u16),
+        wind: i32,
+        config: &Config,
+        glyphs: &GlyphSet,
+        ratio: (u32, u32),
+        rng: &mut Rng,
+    ) -> bool {
+        if wind != 0 {
+            self.drift(wind, cols);
+        }
+        self.tick += 1;
+        let speed = if self.streak { 1 } else { self.speed };
+        if self.tick < speed {
+            return false;
+        }
+        self.tick = 0;
+        self.head += 1;
+        let landed = self.head == rows as i32;
+
+        let ml = self.max_len;
+        self.chars[self.write_pos as usize] = random_glyph(glyphs, ratio, rng);
+        self.write_pos = (self.write_pos + 1) % ml;
+        if self.len < ml {
+            self.len += 1;
+        }
+
+        if self.glitch && self.len > 2 && rng.gen_bool(config.glitch_rate, 1000) {
+            let idx = rng.gen_u32(self.len as u32 - 1) + 1;
+            let ring_idx = (self.write_pos as u32 + ml as u32 - 1 - idx) % ml as u32;
+            self.chars[ring_idx as usize] = random_glyph(glyphs, ratio, rng);
+        }
+
+        let tail_row = self.head - self.len as i32;
+        if tail_row > rows as i32 {
+            self.reset(rows, config, rng);
+        }
+        landed
+    }
+
+    /// Move sideways by `shift` columns, clamped to `0..cols`.
+    fn drift(&mut self, shift: i32, cols: u16) {
+        if cols > 0 {
+            self.col = (self.col as i32 + shift).clamp(0, cols as i32 - 1) as u16;
+        }
+    }
+
+    #[inline(always)]
+    fn trail_glyph(&self, i: u16) -> Glyph {
+        let ml = self.max_len as usize;
+        self.chars[(self.write_pos as usize + ml - 1 - i as usize) % ml]
+    }
+}
+
+// ---------------------------------------------------------------------------
+// --pool: glyphs settling on the bottom rows
+// ---------------------------------------------------------------------------
+
+/// Rows a column's pool may stack up.
+const POOL_DEPTH: usize = 3;
+/// How long a settled glyph lingers before draining away.
+const POOL_SECS: u32 = 4;
+
+/// Head glyphs left behind by drops that reached the bottom, draining after
+/// `POOL_SECS`. Slots are stored bottom row first, `cols` per row.
+struct Pool {
+    cols: usize,
+    /// (glyph, frames left); 0 frames left is an empty slot.
+    slots: Vec<(Glyph, u32)>,
+}
+
+impl Pool {
+    fn new(cols: u16) -> Self {
+        Self {
+            cols: cols as usize,
+            slots: vec![(Glyph::SPACE, 0); cols as usize * POOL_DEPTH],
+        }
+    }
+
+    /// Settle `glyph` on the lowest free slot of `col` for `frames` frames.
+    /// A full column refreshes its top glyph instead of growing.
+    fn settle(&mut self, col: usize, glyph: Glyph, frames: u32) {
+        if col >= self.cols {
+            return;
+        }
+        let depth = (0..POOL_DEPTH)
+            .find(|&d| self.slots[d * self.cols + col].1 == 0)
+            .unwrap_or(POOL_DEPTH - 1);
+        self.slots[depth * self.cols + col] = (glyph, frames);
+    }
+
+    fn drain(&mut self) {
+        for slot in &mut self.slots {
+            slot.1 = slot.1.saturating_sub(1);
+        }
+    }
+
+    /// Occupied slots as (rows above the bottom, column, glyph).
+    fn settled(&self) -> impl Iterator<Item = (usize, usize, Glyph)> + '_ {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.1 > 0)
+            .map(|(i, slot)| (i / self.cols, i % self.cols, slot.0))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Payload menu
+// ---------------------------------------------------------------------------
+
+struct PayloadEntry {
+    name: String,
+    path: PathBuf,
+    kind: ScriptKind,
+    /// Extra arguments passed after the script path, from `payload_args`.
+    args: Vec<String>,
+}
+
+/// Interpreter a payload runs under, from its extension.
+#[derive(Clone, Copy)]
+enum ScriptKind {
+    /// `.ps1`, run with `powershell.exe -File`.
+    PowerShell,
+    /// `.cmd` / `.bat`, spawned directly; std runs it through `cmd.exe`.
+    Batch,
+}
+
+impl ScriptKind {
+    fn from_path(path: &std::path::Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?;
+        if ext.eq_ignore_ascii_case("ps1") {
+            Some(ScriptKind::PowerShell)
+        } else if ext.eq_ignore_ascii_case("cmd") || ext.eq_ignore_ascii_case("bat") {
+            Some(ScriptKind::Batch)
+        } else {
+            None
+        }
+    }
+
+    /// Comment prefix that introduces an in-script `args:` line.
+    fn comment(self) -> &'static str {
+        match self {
+            ScriptKind::PowerShell => "#",
+            ScriptKind::Batch => "rem",
+        }
+    }
+
+    /// The program started to run the script, or `None` for scripts that
+    /// are spawned directly (see `payload_command`).
+    fn interpreter(self) -> Option<&'static str> {
+        match self {
+            ScriptKind::PowerShell => Some("powershell"),
+            ScriptKind::Batch => None,
+        }
+    }
+}
+
+/// PowerShell `-ExecutionPolicy` for `.ps1` payloads (`--exec-policy`).
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ExecPolicy {
+    Bypass,
+    RemoteSigned,
+    AllSigned,
+    Restricted,
+}
+
+impl ExecPolicy {
+    /// Case-insensitive, as PowerShell itself accepts them.
+    fn parse(s: &str) -> Option<Self> {
+        [
+            ExecPolicy::Bypass,
+            ExecPolicy::RemoteSigned,
+            ExecPolicy::AllSigned,
+            ExecPolicy::Restricted,
+        ]
+        .into_iter()
+        .find(|p| p.name().eq_ignore_ascii_case(s))
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ExecPolicy::Bypass => "Bypass",
+            ExecPolicy::RemoteSigned => "RemoteSigned",
+            ExecPolicy::AllSigned => "AllSigned",
+            ExecPolicy::Restricted => "Restricted",
+        }
+    }
+}
+
+struct PayloadCategory {
+    /// Folder path below `payload/`; keys the saved collapsed state.
+    name: String,
+    /// What the menu shows, from `.label` files along the path.
+    label: String,
+    entries: Vec<PayloadEntry>,
+    expanded: bool,
+}
+
+#[derive(Clone)]
+pub enum MenuIndex {
+    Category(usize),
+    Entry(usize, usize),
+}
+
+impl MenuIndex {
+    /// Cursor position for a `Menu::items` row.
+    pub fn from_item((is_cat, ci, ei): (bool, usize, usize)) -> Self {
+        if is_cat {
+            MenuIndex::Category(ci)
+        } else {
+            MenuIndex::Entry(ci, ei)
+        }
+    }
+}
+
+pub struct Menu {
+    /// Folder that was scanned for payloads.
+    payload_dir: PathBuf,
+    /// Whether `payload_dir` existed, to explain an empty menu.
+    payload_dir_found: bool,
+    categories: Vec<PayloadCategory>,
+    /// Cache behind `items()`, see `rebuild_items`.
+    items: Vec<(bool, usize, usize)>,
+    pub cursor: MenuIndex,
+    scroll_offset: usize,
+    /// Case-insensitive substring query; empty shows everything.
+    pub filter: String,
+    /// Typed characters go to `filter` instead of being commands.
+    pub filtering: bool,
+}
+
+/// Collapsed category names, one per line, beside the exe.
+const MENU_STATE_FILE: &str = "menu_state.txt";
+
+/// Names from the menu state file. A missing file means nothing collapsed;
+/// names of categories that no longer exist simply never match.
+fn load_collapsed_categories() -> HashSet<String> {
+    match fs::read_to_string(exe_relative(MENU_STATE_FILE)) {
+        Ok(text) => text
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(String::from)
+            .collect(),
+        Err(e) => {
+            if e.kind() != io::ErrorKind::NotFound {
+                log_failure("read menu_state.txt", &e);
+            }
+            HashSet::new()
+        }
+    }
+}
+
+/// How far below a top-level category folder payloads are still found;
+/// also stops runaway recursion through junction/symlink loops.
+const MAX_PAYLOAD_DEPTH: usize = 8;
+
+/// Sorted subdirectories and script files (see `ScriptKind`) of `dir`.
+fn list_payload_dir(dir: &std::path::Path) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let mut dirs = Vec::new();
+    let mut scripts = Vec::new();
+    if let Some(entries) = read_dir_logged(dir) {
+        for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.is_file() && ScriptKind::from_path(&path).is_some() {
+                scripts.push(path);
+            }
+        }
+    }
+    dirs.sort();
+    scripts.sort();
+    (dirs, scripts)
+}
+
+fn file_name_lossy(path: &std::path::Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Arguments for a payload: the contents of a sidecar `<name>.args` file if
+/// there is one, else an `args:` comment line in the script itself
+/// (`# args: -Target x` or `rem args: /q`). Empty when neither exists.
+fn payload_args(path: &std::path::Path, kind: ScriptKind) -> Vec<String> {
+    if let Ok(text) = fs::read_to_string(path.with_extension("args")) {
+        return split_args(text.trim());
+    }
+    let Ok(script) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    let prefix = kind.comment();
+    script
+        .lines()
+        .find_map(|line| {
+            let line = line.trim_start();
+            let head = line.get(..prefix.len())?;
+            if !head.eq_ignore_ascii_case(prefix) {
+                return None;
+            }
+            line[prefix.len()..].trim_start().strip_prefix("args:")
+        })
+        .map(|rest| split_args(rest.trim()))
+        .unwrap_or_default()
+}
+
+/// Split an argument string on whitespace, keeping double-quoted runs
+/// together (the quotes themselves are dropped).
+fn split_args(text: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut pending = false;
+    for ch in text.chars() {
+        match ch {
+            '"' => {
+                quoted = !quoted;
+                pending = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if pending {
+                    args.push(std::mem::take(&mut current));
+                    pending = false;
+                }
+            }
+            c => {
+                current.push(c);
+                pending = true;
+            }
+        }
+    }
+    if pending {
+        args.push(current);
+    }
+    args
+}
+
+/// Display name for a payload folder: the first non-blank line of its
+/// `.label` file, so `01_recon` can read "Recon" while still sorting by
+/// the folder name. Falls back to the folder name.
+fn dir_label(dir: &std::path::Path) -> String {
+    let label = match fs::read_to_string(dir.join(".label")) {
+        Ok(text) => text.lines().map(str::trim).find(|l| !l.is_empty()).map(String::from),
+        Err(e) => {
+            if e.kind() != io::ErrorKind::NotFound {
+                log_failure(&format!("read {}", dir.join(".label").display()), &e);
+            }
+            None
+        }
+    };
+    label.unwrap_or_else(|| file_name_lossy(dir))
+}
+
+/// Add `dir` as category `name`, then its subfolders depth-first as
+/// `name/sub` categories. Top-level folders are always listed; nested ones
+/// only when they hold scripts.
+fn collect_categories(
+    dir: &std::path::Path,
+    name: String,
+    label: String,
+    depth: usize,
+    collapsed: &HashSet<String>,
+    out: &mut Vec<PayloadCategory>,
+) {
+    let (subdirs, scripts) = list_payload_dir(dir);
+    if depth == 0 || !scripts.is_empty() {
+        out.push(PayloadCategory {
+            expanded: !collapsed.contains(&name),
+            entries: scripts
+                .into_iter()
+                .filter_map(|path| {
+                    let kind = ScriptKind::from_path(&path)?;
+                    Some(PayloadEntry {
+                        name: file_name_lossy(&path),
+                        args: payload_args(&path, kind),
+                        kind,
+                        path,
+                    })
+                })
+                .collect(),
+            name: name.clone(),
+            label: label.clone(),
+        });
+    }
+    if depth < MAX_PAYLOAD_DEPTH {
+        for sub in subdirs {
+            let sub_name = format!("{}/{}", name, file_name_lossy(&sub));
+            let sub_label = format!("{}/{}", label, dir_label(&sub));
+            collect_categories(&sub, sub_name, sub_label, depth + 1, collapsed, out);
+        }
+    }
+}
+
+impl Menu {
+    pub fn load() -> Self {
+        let payload_dir = exe_relative("payload");
+        let collapsed = load_collapsed_categories();
+
+        let mut categories = Vec::new();
+        let (dirs, _) = list_payload_dir(&payload_dir);
+        for dir in dirs {
+            let name = file_name_lossy(&dir);
+            let label = dir_label(&dir);
+            collect_categories(&dir, name, label, 0, &collapsed, &mut categories);
+        }
+
+        let mut menu = Menu {
+            payload_dir_found: payload_dir.is_dir(),
+            payload_dir,
+            categories,
+            items: Vec::new(),
+            cursor: MenuIndex::Category(0),
+            scroll_offset: 0,
+            filter: String::new(),
+            filtering: false,
+        };
+        menu.rebuild_items();
+        menu
+    }
+
+    /// `--list`: the scanned folder, then each category with its scripts'
+    /// full paths and any extra arguments, one per line.
+    pub fn write_tree(&self, out: &mut impl Write) -> io::Result<()> {
+        let found = if self.payload_dir_found { "" } else { " (not found)" };
+        writeln!(out, "{}{}", self.payload_dir.display(), found)?;
+        for cat in &self.categories {
+            if cat.label == cat.name {
+                writeln!(out, "{}/", cat.name)?;
+            } else {
+                writeln!(out, "{}/  ({})", cat.name, cat.label)?;
+            }
+            for entry in &cat.entries {
+                write!(out, "    {}", entry.path.display())?;
+                if !entry.args.is_empty() {
+                    write!(out, "  args: {:?}", entry.args)?;
+                }
+                writeln!(out)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remember which categories are collapsed for the next run.
+    pub fn save_state(&self) {
+        let mut text = String::new();
+        for cat in self.categories.iter().filter(|c| !c.expanded) {
+            text.push_str(&cat.name);
+            text.push('\n');
+        }
+        if let Err(e) = fs::write(exe_relative(MENU_STATE_FILE), text) {
+            log_failure("write menu_state.txt", &e);
+        }
+    }
+
+    /// Flattened (is_category, category, entry) rows currently listed.
+    fn items(&self) -> &[(bool, usize, usize)] {
+        &self.items
+    }
+
+    /// Refill the `items` cache, reusing its allocation. Must run after any
+    /// change to `expanded` or `filter`. With a filter, a category shows if
+    /// its label or any entry matches, and its matching entries are listed
+    /// even when collapsed (all of them when the category name matches).
+    fn rebuild_items(&mut self) {
+        let needle = self.filter.to_lowercase();
+        let hit = |name: &str| needle.is_empty() || name.to_lowercase().contains(&needle);
+        self.items.clear();
+        for (ci, cat) in self.categories.iter().enumerate() {
+            let cat_hit = hit(&cat.label);
+            if !cat_hit && !cat.entries.iter().any(|e| hit(&e.name)) {
+                continue;
+            }
+            self.items.push((true, ci, 0));
+            if cat.expanded || !needle.is_empty() {
+                for (ei, entry) in cat.entries.iter().enumerate() {
+                    if cat_hit || hit(&entry.name) {
+                        self.items.push((false, ci, ei));
+                    }
+                }
+            }
+        }
+    }
+
+    fn set_expanded(&mut self, ci: usize, expanded: bool) {
+        self.categories[ci].expanded = expanded;
+        self.rebuild_items();
+    }
+
+    /// First listed line (past the instructions and spacer) shown in a menu
+    /// body `visible_height` lines tall, keeping the cursor in view.
+    fn scroll_for(&self, visible_height: usize) -> usize {
+        let line_count = 2 + self.items.len().max(1);
+        let mut scroll = self.scroll_offset;
+        if line_count > visible_height {
+            let cursor_line = 2 + self.cursor_flat_index();
+            if cursor_line < scroll + 2 {
+                scroll = cursor_line.saturating_sub(2);
+            } else if cursor_line + 2 >= scroll + visible_height {
+                scroll = cursor_line.saturating_sub(visible_height.saturating_sub(3));
+            }
+        } else {
+            scroll = 0;
+        }
+        scroll
+    }
+
+    /// The listed row drawn at screen cell (`r`, `c`) inside `rect`.
+    pub fn item_at(&self, rect: MenuRect, r: usize, c: usize) -> Option<(bool, usize, usize)> {
+        let inner_h = rect.h.saturating_sub(2);
+        if c <= rect.x || c + 1 >= rect.x + rect.w || r <= rect.y || r > rect.y + inner_h {
+            return None;
+        }
+        let line = r - rect.y - 1 + self.scroll_for(inner_h);
+        self.items.get(line.checked_sub(2)?).copied()
+    }
+
+    /// Whether the cursor is on a row that is currently listed.
+    pub fn cursor_visible(&self) -> bool {
+        self.items.iter().any(|&item| self.cursor_is(item))
+    }
+
+    /// The cursor, if it is on a listed row. With no categories, or a
+    /// filter that matches nothing, there is nothing to act on.
+    fn selected(&self) -> Option<MenuIndex> {
+        self.cursor_visible().then(|| self.cursor.clone())
+    }
+
+    /// Left: fold the cursor's category, moving up to it from an entry.
+    pub fn collapse_selected(&mut self) {
+        match self.selected() {
+            Some(MenuIndex::Entry(ci, _)) => {
+                self.set_expanded(ci, false);
+                self.cursor = MenuIndex::Category(ci);
+            }
+            Some(MenuIndex::Category(ci)) => self.set_expanded(ci, false),
+            None => {}
+        }
+    }
+
+    /// Right: unfold the category under the cursor.
+    pub fn expand_selected(&mut self) {
+        if let Some(MenuIndex::Category(ci)) = self.selected() {
+            self.set_expanded(ci, true);
+        }
+    }
+
+    fn cursor_is(&self, (is_cat, ci, ei): (bool, usize, usize)) -> bool {
+        match &self.cursor {
+            MenuIndex::Category(c) => is_cat && *c == ci,
+            MenuIndex::Entry(c, e) => !is_cat && *c == ci && *e == ei,
+        }
+    }
+
+    pub fn push_filter(&mut self, ch: char) {
+        self.filter.push(ch);
+        self.rebuild_items();
+        self.sync_cursor();
+    }
+
+    pub fn pop_filter(&mut self) {
+        self.filter.pop();
+        self.rebuild_items();
+        self.sync_cursor();
+    }
+
+    pub fn clear_filter(&mut self) {
+        self.filter.clear();
+        self.filtering = false;
+        self.rebuild_items();
+        self.sync_cursor();
+    }
+
+    /// Move the cursor to the first listed row if the filter hid it.
+    fn sync_cursor(&mut self) {
+        if self.cursor_visible() {
+            return;
+        }
+        if let Some(&item) = self.items.first() {
+            self.cursor = MenuIndex::from_item(item);
+        }
+    }
+
+    fn cursor_flat_index(&self) -> usize {
+        self.items
+            .iter()
+            .position(|&item| self.cursor_is(item))
+            .unwrap_or(0)
+    }
+
+    pub fn move_up(&mut self) {
+        self.move_by(-1);
+    }
+
+    pub fn move_down(&mut self) {
+        self.move_by(1);
+    }
+
+    pub fn move_home(&mut self) {
+        self.move_by(isize::MIN);
+    }
+
+    pub fn move_end(&mut self) {
+        self.move_by(isize::MAX);
+    }
+
+    /// Move up by `page` rows (one screenful of the menu body).
+    pub fn page_up(&mut self, page: usize) {
+        self.move_by(-(page.max(1) as isize));
+    }
+
+    pub fn page_down(&mut self, page: usize) {
+        self.move_by(page.max(1) as isize);
+    }
+
+    /// Move the cursor `delta` listed rows, stopping at either end.
+    fn move_by(&mut self, delta: isize) {
+        if self.items.is_empty() {
+            return;
+        }
+        let idx = self
+            .cursor_flat_index()
+            .saturating_add_signed(delta)
+            .min(self.items.len() - 1);
+        self.cursor = MenuIndex::from_item(self.items[idx]);
+    }
+}
+
+/// The interpreter invocation for `entry`, before stdio is wired up.
+///
+/// The script path is made absolute but not canonicalized: the `\\?\`
+/// prefix `canonicalize` adds on Windows is rejected by `powershell -File`.
+/// Batch files are spawned directly rather than as `cmd.exe /C <path>`:
+/// `/C` strips the first and last quote of the rest of the line, which
+/// breaks a quoted path with spaces once arguments follow it, while std
+/// quotes batch invocations for cmd itself.
+fn payload_command(entry: &PayloadEntry, policy: ExecPolicy) -> Command {
+    let path = std::path::absolute(&entry.path).unwrap_or_else(|_| entry.path.clone());
+    let mut command = match entry.kind {
+        ScriptKind::PowerShell => {
+            let mut c = Command::new("powershell.exe");
+            c.args(["-ExecutionPolicy", policy.name(), "-File"]).arg(path);
+            c
+        }
+        ScriptKind::Batch => Command::new(path),
+    };
+    command.args(&entry.args);
+    command
+}
+
+/// Start a payload under its interpreter. Its stdout and stderr are appended
+/// to `payload.log` beside the exe (so they neither land on top of the rain
+/// nor vanish); if the log can't be opened the output is discarded. Stdin
+/// is inherited, so scripts that prompt (`Read-Host`, `pause`) still read
+/// the console.
+fn launch_payload(entry: &PayloadEntry, policy: ExecPolicy) -> io::Result<Child> {
+    let (stdout, stderr) = match payload_log() {
+        Ok((out, err)) => (Stdio::from(out), Stdio::from(err)),
+        Err(e) => {
+            log_failure("open payload.log", &e);
+            (Stdio::null(), Stdio::null())
+        }
+    };
+    let spawned = payload_command(entry, policy)
+        .stdout(stdout)
+        .stderr(stderr)
+        .spawn();
+    if let Err(e) = &spawned {
+        log_failure(&format!("launch {}", entry.path.display()), e);
+    }
+    spawned
+}
+
+/// Two append handles on `payload.log`, headed by a line naming the launch.
+fn payload_log() -> io::Result<(fs::File, fs::File)> {
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(exe_relative("payload.log"))?;
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    writeln!(file, "--- launch timestamp={}", timestamp)?;
+    let err = file.try_clone()?;
+    Ok((file, err))
+}
+
+/// Status line for a launch attempt.
+fn launch_outcome(entry: &PayloadEntry, result: &io::Result<Child>) -> LaunchMessage {
+    let (text, error) = match result {
+        Ok(_) => (format!("Launched: {}", entry.name), false),
+        // Missing interpreter, or for a directly spawned script the
+        // script itself
+        Err(e) if e.kind() == io::ErrorKind::NotFound => match entry.kind.interpreter() {
+            Some(program) => (format!("Failed: {} not found", program), true),
+            None => (format!("Failed: {} not found", entry.path.display()), true),
+        },
+        Err(e) => (format!("Failed: {}", e), true),
+    };
+    LaunchMessage { text, error, shown: Instant::now() }
+}
+
+// ---------------------------------------------------------------------------
+// Diagnostics: --verbose failure log
+// ---------------------------------------------------------------------------
+
+pub static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+/// `name` in the directory holding the executable (cwd-relative fallback).
+fn exe_relative(name: &str) -> PathBuf {
+    std::env::current_exe()
+        .ok()
+        .and_then(|p| p.parent().map(|d| d.join(name)))
+        .unwrap_or_else(|| PathBuf::from(name))
+}
+
+/// Append a failure record to `badderblood.log` beside the exe when
+/// `--verbose` is on. Stderr is not used since it shares the rain's console.
+pub fn log_failure(what: &str, err: &io::Error) {
+    if !VERBOSE.load(Ordering::Relaxed) {
+        return;
+    }
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let code = err
+        .raw_os_error()
+        .map_or_else(|| "-".to_string(), |c| c.to_string());
+    let line = format!(
+        "timestamp={} what=\"{}\" code={} error=\"{}\"\n",
+        timestamp, what, code, err
+    );
+    if let Ok(mut f) = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(exe_relative("badderblood.log"))
+    {
+        let _ = f.write_all(line.as_bytes());
+    }
+}
+
+fn read_dir_logged(dir: &std::path::Path) -> Option<fs::ReadDir> {
+    fs::read_dir(dir)
+        .map_err(|e| log_failure(&format!("read dir {}", dir.display()), &e))
+        .ok()
+}
+
+// ---------------------------------------------------------------------------
+// Input actions
+// ---------------------------------------------------------------------------
+
+/// What a frame of console input asks for, decoded by the platform layer.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum InputAction {
+    Quit,
+    /// The menu key (Tab or Enter unless remapped) with the menu closed.
+    Tab,
+    Enter,
+    Escape,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Restart,
+    /// `r`: send every drop back to the top with a fresh roll.
+    Reset,
+    Pause,
+    ToggleFps,
+    /// `c`: switch to the next colour theme.
+    CycleTheme,
+    /// `/`: start typing a menu filter.
+    Search,
+    /// Printable character while typing a menu filter.
+    Char(char),
+    Backspace,
+    /// Answer to the launch prompt: `y`/`Y` is yes, any other key no.
+    Confirm(bool),
+    /// Left click at window-relative console (column, row).
+    Click(u16, u16),
+    Resize(u16, u16),
+}
+
+impl InputAction {
+    /// Whether applying this can change the `InputMode` the next key is
+    /// read under (menu open, filter typing, launch prompt).
+    pub fn changes_mode(&self) -> bool {
+        matches!(
+            self,
+            InputAction::Quit
+                | InputAction::Tab
+                | InputAction::Enter
+                | InputAction::Escape
+                | InputAction::Search
+                | InputAction::Confirm(_)
+                | InputAction::Click(..)
+        )
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Key bindings: --key-quit / --key-menu / --key-pause
+// ---------------------------------------------------------------------------
+
+/// One bound key: a typed character, or a Win32 virtual-key code for keys
+/// that type nothing (Tab and Enter do type, but are matched by code so
+/// Ctrl+I / Ctrl+M don't count).
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(u16),
+    Vk(u16),
+}
+
+const VK_TAB: u16 = 0x09;
+const VK_RETURN: u16 = 0x0D;
+
+impl Key {
+    /// A single character, or a virtual-key code in decimal or `0x` hex
+    /// (`0x70` is F1). A lone digit is always the character key; prefix
+    /// `vk:` to mean a code instead (`vk:9` is Tab, same as `0x09`).
+    fn parse(s: &str) -> Option<Key> {
+        if let Some(code) = s.strip_prefix("vk:") {
+            return Key::parse_code(code).map(Key::Vk);
+        }
+        let mut chars = s.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return u16::try_from(c as u32).ok().map(Key::Char);
+        }
+        Key::parse_code(s).map(Key::Vk)
+    }
+
+    fn parse_code(s: &str) -> Option<u16> {
+        match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(hex) => u16::from_str_radix(hex, 16).ok(),
+            None => s.parse().ok(),
+        }
+    }
+
+    /// ASCII letters match either case, so a bound key still works with
+    /// Shift or Caps Lock.
+    fn matches(self, vk: u16, ch: u16) -> bool {
+        match self {
+            Key::Char(c) if c < 0x80 && ch < 0x80 => (c as u8).eq_ignore_ascii_case(&(ch as u8)),
+            Key::Char(c) => ch == c,
+            Key::Vk(v) => vk == v,
+        }
+    }
+
+    /// Short name for the status bar hint.
+    fn label(self) -> String {
+        match self {
+            Key::Char(0x20) => "Space".to_string(),
+            Key::Char(c) => char::from_u32(c as u32).map_or_else(String::new, String::from),
+            Key::Vk(VK_TAB) => "Tab".to_string(),
+            Key::Vk(VK_RETURN) => "Enter".to_string(),
+            Key::Vk(v) => format!("key 0x{:02X}", v),
+        }
+    }
+}
+
+/// Keys for the remappable actions. Each action keeps its defaults until
+/// an option names a key for it, which then replaces them.
+#[derive(Clone)]
+pub struct KeyMap {
+    quit: Vec<Key>,
+    menu: Vec<Key>,
+    pause: Vec<Key>,
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        Self {
+            quit: vec![Key::Char(b'q' as u16), Key::Char(b'Q' as u16)],
+            menu: vec![Key::Vk(VK_TAB), Key::Vk(VK_RETURN)],
+            pause: vec![Key::Char(b' ' as u16)],
+        }
+    }
+}
+
+impl KeyMap {
+    /// The action a key press is bound to. The menu key only opens the
+    /// menu, so with the menu up Enter falls through to selecting.
+    pub fn action(&self, vk: u16, ch: u16, menu_open: bool) -> Option<InputAction> {
+        let bound = |keys: &[Key]| keys.iter().any(|k| k.matches(vk, ch));
+        if bound(&self.quit) {
+            Some(InputAction::Quit)
+        } else if !menu_open && bound(&self.menu) {
+            Some(InputAction::Tab)
+        } else if bound(&self.pause) {
+            Some(InputAction::Pause)
+        } else {
+            None
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Command-line options
+// ---------------------------------------------------------------------------
+
+#[derive(Clone)]
+pub struct Config {
+    /// Leave the menu open after launching a payload.
+    keep_menu_open: bool,
+    /// How long a launch message stays up.
+    message_duration: Duration,
+    /// Start with the menu open and keep it there; Escape quits.
+    pub menu_first: bool,
+    /// Render every other row one brightness step dimmer.
+    scanlines: bool,
+    /// Roll a dimmer band down the rain, like a CRT's refresh flicker.
+    flicker: bool,
+    /// Leave black backgrounds to the terminal's own, so a custom
+    /// background or wallpaper shows behind the rain.
+    pub transparent: bool,
+    /// Keep each drop's speed for its whole lifetime instead of re-rolling on reset.
+    fixed_column_speed: bool,
+    /// Diff only the cells rendering touched instead of the whole grid.
+    /// Pays off when little of the screen changes (`--no-rain`, a mostly
+    /// idle menu); with default rain the bookkeeping costs more than the
+    /// full-grid compare it saves.
+    pub dirty_tracking: bool,
+    /// `q` closes the menu (like Escape) instead of being ignored while it is open.
+    pub q_closes_menu: bool,
+    /// Animate the first menu open, sweeping the rain aside to reveal the box.
+    fancy_menu_open: bool,
+    /// Smallest (cols, rows) the rain and menu are drawn at.
+    min_size: (u16, u16),
+    /// Fixed (cols, rows) to draw at regardless of the window, for
+    /// recordings; window resizes are ignored while set.
+    pub size: Option<(u16, u16)>,
+    /// Colour emission path for the rain.
+    color_mode: ColorMode,
+    /// Hue of the rain, status bar and menu.
+    theme: ColorTheme,
+    /// Shape of the fade from head to tail.
+    decay: DecayCurve,
+    /// Execution policy `.ps1` payloads run under.
+    exec_policy: ExecPolicy,
+    /// `--direction up` makes the streams rise.
+    direction: Direction,
+    /// Per-drop brightness jitter in percent (0 = uniform).
+    brightness_variation: u8,
+    /// Glyph file to draw the rain from instead of the built-in set.
+    pub glyphs: Option<PathBuf>,
+    /// Built-in set to use when there is no glyph file (or it won't load).
+    pub glyph_preset: GlyphPreset,
+    /// Fraction of glyphs drawn from half-width katakana, as N/D.
+    katakana_ratio: (u32, u32),
+    /// Keep drawing rain cells underneath the open menu.
+    rain_behind_menu: bool,
+    /// Dim head, brightening toward the tail.
+    invert_fade: bool,
+    /// Largest number of bytes handed to a single WriteFile call.
+    pub write_chunk: usize,
+    /// Keep the rain moving while the menu is open instead of freezing it.
+    pub animate_under_menu: bool,
+    /// Periodically start the animation over (long-running signage).
+    pub restart_interval: Option<Duration>,
+    /// Fade the rain down after this long without input.
+    idle_dim_after: Option<Duration>,
+    /// Log otherwise-ignored failures to badderblood.log.
+    pub verbose: bool,
+    /// Target frame rate, clamped to `FPS_RANGE`.
+    pub fps: u32,
+    /// Multiplier on the extra drops, clamped to `DENSITY_RANGE`.
+    density: f32,
+    /// Columns that get all the extra drops, picked at random, instead of
+    /// spreading them evenly. 0 keeps the even spread.
+    hot_columns: u16,
+    /// Exit on any key press or mouse event, like an idle screensaver.
+    pub screensaver: bool,
+    /// Render this many frames flat out, print timings and exit.
+    pub bench: Option<u64>,
+    /// Shortest and longest trail a drop may roll; the longest is also
+    /// capped at the screen height, which the default leaves as the limit.
+    trail_min: u16,
+    trail_max: u16,
+    /// Slowest and fastest drop speed in frames per step; 1 moves every
+    /// frame, so lower values make faster rain.
+    speed_min: u8,
+    speed_max: u8,
+    /// Bright leading cells of a drop: the head plus `head_len - 1`
+    /// near-head cells before the trail fade begins.
+    head_len: u16,
+    /// Draw the frame counter / key hints in the bottom-right corner.
+    show_status: bool,
+    /// What the status bar shows after the title, in order.
+    status_fields: Vec<StatusField>,
+    /// Per-mille of drops that glitch (0 disables the effect).
+    glitch_chance: u32,
+    /// Per-mille chance per step that a glitching drop swaps a trail glyph.
+    glitch_rate: u32,
+    /// Fixed PRNG seed for reproducible rain instead of the clock.
+    seed: Option<u64>,
+    /// Sideways drift in columns per frame, within -1..=1; positive blows
+    /// right.
+    wind: f32,
+    /// Animate the rain; off leaves a black screen behind the status bar
+    /// and menu.
+    rain_enabled: bool,
+    /// Colour the rain in scrolling vertical bands instead of the theme.
+    rainbow: bool,
+    /// Let drops that reach the bottom leave a draining pool of glyphs.
+    pool: bool,
+    /// Log average update / render / diff time per frame with each fps line.
+    pub profile: bool,
+    /// Layout of the lines written to the fps logs.
+    pub fps_format: FpsFormat,
+    /// Console window title while running.
+    pub title: String,
+    /// Repaint every cell each frame instead of diffing, for terminals
+    /// that leave stale characters behind.
+    pub full_repaint: bool,
+    /// Draw drop heads bold as well as bright.
+    bold_head: bool,
+    /// Also append every byte sent to the console to this file, so the
+    /// VT stream can be replayed with `cat`.
+    pub record: Option<PathBuf>,
+    /// Run extra updates after a slow frame so the rain keeps real-time pace.
+    pub catch_up: bool,
+    /// `--message`: text revealed at screen centre every
+    /// `hidden_message_every`, then dissolved back into the rain.
+    hidden_message: Option<String>,
+    hidden_message_every: Duration,
+    /// Remapped quit / menu / pause keys.
+    pub keys: KeyMap,
+    /// Print the discovered payload tree and exit without rendering.
+    pub list: bool,
+    /// Options whose current value came from the config file, with the key
+    /// as written there and its `path:line`, so errors point at the file.
+    file_origins: HashMap<String, (String, String)>,
+}
+
+const DENSITY_RANGE: std::ops::RangeInclusive<f32> = 0.1..=5.0;
+
+const FPS_RANGE: std::ops::RangeInclusive<u32> = 1..=240;
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            keep_menu_open: false,
+            message_duration: Duration::from_secs(3),
+            menu_first: false,
+            scanlines: false,
+            flicker: false,
+            transparent: false,
+            fixed_column_speed: false,
+            dirty_tracking: false,
+            q_closes_menu: false,
+            fancy_menu_open: false,
+            min_size: (10, 3),
+            size: None,
+            color_mode: ColorMode::Ansi16,
+            theme: ColorTheme::Green,
+            decay: DecayCurve::Linear,
+            exec_policy: ExecPolicy::Bypass,
+            direction: Direction::Down,
+            brightness_variation: 0,
+            glyphs: None,
+            glyph_preset: GlyphPreset::Matrix,
+            katakana_ratio: (0, 1),
+            rain_behind_menu: false,
+            invert_fade: false,
+            write_chunk: 16 * 1024,
+            animate_under_menu: false,
+            restart_interval: None,
+            idle_dim_after: None,
+            verbose: false,
+            fps: 30,
+            density: 1.0,
+            hot_columns: 0,
+            screensaver: false,
+            bench: None,
+            trail_min: 8,
+            trail_max: u16::MAX,
+            speed_min: 1,
+            speed_max: 4,
+            head_len: 3,
+            show_status: true,
+            status_fields: vec![StatusField::Frame, StatusField::Keys],
+            glitch_chance: 350,
+            glitch_rate: 300,
+            seed: None,
+            wind: 0.0,
+            rain_enabled: true,
+            rainbow: false,
+            pool: false,
+            profile: false,
+            fps_format: FpsFormat::Text,
+            title: "BadderBlood".to_string(),
+            full_repaint: false,
+            bold_head: false,
+            catch_up: false,
+            hidden_message: None,
+            hidden_message_every: Duration::from_secs(20),
+            keys: KeyMap::default(),
+            list: false,
+            record: None,
+            file_origins: HashMap::new(),
+        }
+    }
+}
+
+/// Where `Config::set` takes an option's value from.
+enum OptionSource<'a> {
+    /// Command line: flags stand alone, other options consume the next
+    /// argument.
+    Args(&'a mut dyn Iterator<Item = String>),
+    /// Config file: every key carries its value, along with the key as
+    /// written and the `path:line` it is on.
+    Inline { value: String, key: String, at: String },
+}
+
+impl OptionSource<'_> {
+    fn value(&mut self, name: &str) -> Result<String, String> {
+        match self {
+            OptionSource::Args(args) => args
+                .next()
+                .ok_or_else(|| format!("option '--{}' needs a value", name)),
+            OptionSource::Inline { value, .. } => Ok(std::mem::take(value)),
+        }
+    }
+
+    /// A switch: present on the command line, `true`/`false` in the file.
+    fn flag(&mut self, name: &str) -> Result<bool, String> {
+        match self {
+            OptionSource::Args(_) => Ok(true),
+            OptionSource::Inline { value, .. } => match value.as_str() {
+                "true" => Ok(true),
+                "false" => Ok(false),
+                _ => Err(format!("'{}' must be true or false", name)),
+            },
+        }
+    }
+}
+
+/// Settings file read from beside the exe before the command line.
+const CONFIG_FILE: &str = "badderblood.toml";
+
+impl Config {
+    /// Settings are layered, later sources winning: built-in defaults, then
+    /// `badderblood.toml` beside the exe (if present), then command-line
+    /// flags.
+    pub fn from_args() -> Result<Self, String> {
+        let mut config = Config::default();
+        config.apply_file(&exe_relative(CONFIG_FILE))?;
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let known = match arg.strip_prefix("--") {
+                Some(name) => config.set_from(name, &mut OptionSource::Args(&mut args))?,
+                None => false,
+            };
+            if !known {
+                return Err(format!("unknown option '{}'", arg));
+            }
+        }
+        config.check_ranges()?;
+        Ok(config)
+    }
+
+    /// Reject min/max pairs that cross, naming wherever each bound was set.
+    fn check_ranges(&self) -> Result<(), String> {
+        if self.trail_min > self.trail_max {
+            return Err(format!(
+                "{} is greater than {}",
+                self.describe("trail-min", self.trail_min),
+                self.describe("trail-max", self.trail_max)
+            ));
+        }
+        if self.speed_min > self.speed_max {
+            return Err(format!(
+                "{} is greater than {}",
+                self.describe("speed-min", self.speed_min),
+                self.describe("speed-max", self.speed_max)
+            ));
+        }
+        Ok(())
+    }
+
+    /// `name` and its `value` as the user gave it: the file key and line if
+    /// the config file set it, otherwise the command-line flag.
+    fn describe(&self, name: &str, value: impl std::fmt::Display) -> String {
+        match self.file_origins.get(name) {
+            Some((key, at)) => format!("{} = {} ({})", key, value, at),
+            None => format!("--{} {}", name, value),
+        }
+    }
+
+    /// `set`, remembering whether the value came from the config file.
+    fn set_from(&mut self, name: &str, source: &mut OptionSource) -> Result<bool, String> {
+        let known = self.set(name, source)?;
+        if known {
+            match source {
+                OptionSource::Args(_) => {
+                    self.file_origins.remove(name);
+                }
+                OptionSource::Inline { key, at, .. } => {
+                    self.file_origins.insert(name.to_string(), (key.clone(), at.clone()));
+                }
+            }
+        }
+        Ok(known)
+    }
+
+    /// Apply a config file of flat `key = value` lines: keys are the
+    /// command-line option names without `--` (`_` may stand for `-`),
+    /// values are bare (`fps = 60`, `scanlines = true`) or quoted
+    /// (`theme = "amber"`, `glyphs = 'C:\glyphs.txt'`), and `#` starts a
+    /// comment. A missing file is not an error.
+    fn apply_file(&mut self, path: &std::path::Path) -> Result<(), String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        for (n, line) in text.lines().enumerate() {
+            let at = |msg: String| format!("{}:{}: {}", path.display(), n + 1, msg);
+            let line = strip_toml_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| at("expected key = value".to_string()))?;
+            let name = key.trim().replace('_', "-");
+            let mut source = OptionSource::Inline {
+                value: unquote_toml(value.trim()),
+                key: key.trim().to_string(),
+                at: format!("{}:{}", path.display(), n + 1),
+            };
+            if !self.set_from(&name, &mut source).map_err(at)? {
+                return Err(at(format!("unknown key '{}'", key.trim())));
+            }
+        }
+        Ok(())
+    }
+
+    /// Set option `name` (without the leading `--`) from `source`.
+    /// Returns `Ok(false)` for an unknown name.
+    fn set(&mut self, name: &str, source: &mut OptionSource) -> Result<bool, String> {
+        match name {
+            "keep-menu-open" => self.keep_menu_open = source.flag(name)?,
+            "once" | "menu" => self.menu_first = source.flag(name)?,
+            "scanlines" => self.scanlines = source.flag(name)?,
+            "flicker" => self.flicker = source.flag(name)?,
+            "transparent" => self.transparent = source.flag(name)?,
+            "fixed-column-speed" => self.fixed_column_speed = source.flag(name)?,
+            "dirty-tracking" => self.dirty_tracking = source.flag(name)?,
+            "q-closes-menu" => self.q_closes_menu = source.flag(name)?,
+            "fancy-menu-open" => self.fancy_menu_open = source.flag(name)?,
+            "min-size" => {
+                let v = source.value(name)?;
+                self.min_size = parse_size(&v)
+                    .ok_or_else(|| format!("invalid size '{}' (expected WxH)", v))?;
+            }
+            "size" => {
+                let v = source.value(name)?;
+                let size = parse_size(&v)
+                    .filter(|&(w, h)| w > 0 && h > 0)
+                    .ok_or_else(|| format!("invalid size '{}' (expected WxH)", v))?;
+                self.size = Some(size);
+            }
+            "truecolor" => {
+                if source.flag(name)? {
+                    self.color_mode = ColorMode::TrueColor;
+                }
+            }
+            "color-mode" => {
+                let v = source.value(name)?;
+                self.color_mode = ColorMode::parse(&v).ok_or_else(|| {
+                    format!("unknown color mode '{}' (expected 4bit, 256 or truecolor)", v)
+                })?;
+            }
+            "theme" => {
+                let v = source.value(name)?;
+                self.theme = ColorTheme::parse(&v).ok_or_else(|| {
+                    let names: Vec<_> = ColorTheme::ALL.iter().map(|t| t.name()).collect();
+                    format!("unknown theme '{}' (expected {})", v, names.join(", "))
+                })?;
+            }
+            "exec-policy" => {
+                let v = source.value(name)?;
+                self.exec_policy = ExecPolicy::parse(&v).ok_or_else(|| {
+                    format!(
+                        "unknown execution policy '{}' \
+                         (expected Bypass, RemoteSigned, AllSigned or Restricted)",
+                        v
+                    )
+                })?;
+            }
+            "decay" => {
+                let v = source.value(name)?;
+                self.decay = DecayCurve::parse(&v).ok_or_else(|| {
+                    format!("unknown decay curve '{}' (expected linear, exp or stepped)", v)
+                })?;
+            }
+            "direction" => {
+                let v = source.value(name)?;
+                self.direction = Direction::parse(&v)
+                    .ok_or_else(|| format!("unknown direction '{}' (expected down or up)", v))?;
+            }
+            "brightness-variation" => {
+                let v = source.value(name)?;
+                self.brightness_variation = v
+                    .parse()
+                    .ok()
+                    .filter(|&p| p <= 100)
+                    .ok_or_else(|| format!("invalid brightness variation '{}' (0-100)", v))?;
+            }
+            "glyphs" | "charset" => self.glyphs = Some(PathBuf::from(source.value(name)?)),
+            "glyphset" => {
+                let v = source.value(name)?;
+                self.glyph_preset = GlyphPreset::parse(&v).ok_or_else(|| {
+                    format!("unknown glyph set '{}' (expected matrix, binary, hex or ascii)", v)
+                })?;
+            }
+            "katakana-ratio" => {
+                let v = source.value(name)?;
+                self.katakana_ratio = parse_ratio(&v).ok_or_else(|| {
+                    format!("invalid katakana ratio '{}' (expected N/D with N <= D, D > 0)", v)
+                })?;
+            }
+            "rain-behind-menu" => self.rain_behind_menu = source.flag(name)?,
+            "invert-fade" => self.invert_fade = source.flag(name)?,
+            "animate-under-menu" => self.animate_under_menu = source.flag(name)?,
+            "verbose" => self.verbose = source.flag(name)?,
+            "screensaver" => self.screensaver = source.flag(name)?,
+            "no-status-bar" => self.show_status = !source.flag(name)?,
+            "status-fields" => {
+                let v = source.value(name)?;
+                self.status_fields = v
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|f| !f.is_empty())
+                    .map(|f| {
+                        StatusField::parse(f).ok_or_else(|| {
+                            format!(
+                                "unknown status field '{}' \
+                                 (expected frame, fps, drops, time or keys)",
+                                f
+                            )
+                        })
+                    })
+                    .collect::<Result<_, _>>()?;
+            }
+            "no-rain" => self.rain_enabled = !source.flag(name)?,
+            "rainbow" => self.rainbow = source.flag(name)?,
+            "pool" => self.pool = source.flag(name)?,
+            "profile" => self.profile = source.flag(name)?,
+            "fps-format" => {
+                let v = source.value(name)?;
+                self.fps_format = FpsFormat::parse(&v)
+                    .ok_or_else(|| format!("unknown fps format '{}' (expected text or json)", v))?;
+            }
+            "title" => self.title = source.value(name)?,
+            "record" => self.record = Some(PathBuf::from(source.value(name)?)),
+            "full-repaint" => self.full_repaint = source.flag(name)?,
+            "bold-head" => self.bold_head = source.flag(name)?,
+            "catch-up" => self.catch_up = source.flag(name)?,
+            "bench" => {
+                let v = source.value(name)?;
+                let frames = v
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| format!("invalid bench frame count '{}'", v))?;
+                self.bench = Some(frames);
+            }
+            "fps" => {
+                let v = source.value(name)?;
+                let fps: u32 = v
+                    .parse()
+                    .map_err(|_| format!("invalid fps '{}'", v))?;
+                self.fps = fps.clamp(*FPS_RANGE.start(), *FPS_RANGE.end());
+            }
+            "restart-interval" => {
+                let v = source.value(name)?;
+                let secs: u64 = v
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| format!("invalid restart interval '{}' (seconds)", v))?;
+                self.restart_interval = Some(Duration::from_secs(secs));
+            }
+            "list" => self.list = source.flag(name)?,
+            "key-quit" | "key-menu" | "key-pause" => {
+                let v = source.value(name)?;
+                let key = Key::parse(&v).ok_or_else(|| {
+                    format!("invalid key '{}' (a character, or a code like 0x70 or vk:9)", v)
+                })?;
+                let keys = match name {
+                    "key-quit" => &mut self.keys.quit,
+                    "key-menu" => &mut self.keys.menu,
+                    _ => &mut self.keys.pause,
+                };
+                *keys = vec![key];
+            }
+            "message" => self.hidden_message = Some(source.value(name)?),
+            "message-every" => {
+                let v = source.value(name)?;
+                let secs: u64 = v
+                    .parse()
+                    .ok()
+                    // A reveal has to finish before the next one starts
+                    .filter(|&n| n as f32 >= HIDDEN_MESSAGE_SECS)
+                    .ok_or_else(|| {
+                        format!(
+                            "invalid message interval '{}' (seconds, at least {})",
+                            v, HIDDEN_MESSAGE_SECS
+                        )
+                    })?;
+                self.hidden_message_every = Duration::from_secs(secs);
+            }
+            "message-secs" => {
+                let v = source.value(name)?;
+                let secs: u64 = v
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| format!("invalid message duration '{}' (seconds)", v))?;
+                self.message_duration = Duration::from_secs(secs);
+            }
+            "idle-dim-secs" => {
+                let v = source.value(name)?;
+                let secs: u64 = v
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| format!("invalid idle dim delay '{}' (seconds)", v))?;
+                self.idle_dim_after = Some(Duration::from_secs(secs));
+            }
+            "density" => {
+                let v = source.value(name)?;
+                let density: f32 = v
+                    .parse()
+                    .ok()
+                    .filter(|d: &f32| d.is_finite())
+                    .ok_or_else(|| format!("invalid density '{}'", v))?;
+                self.density = density.clamp(*DENSITY_RANGE.start(), *DENSITY_RANGE.end());
+            }
+            "hot-columns" => {
+                let v = source.value(name)?;
+                self.hot_columns = v
+                    .parse()
+                    .map_err(|_| format!("invalid hot column count '{}'", v))?;
+            }
+            "head-len" => {
+                let v = source.value(name)?;
+                self.head_len = v
+                    .parse()
+                    .ok()
+                    .filter(|&n: &u16| n >= 1)
+                    .ok_or_else(|| format!("invalid head length '{}' (>= 1)", v))?;
+            }
+            "trail-min" | "trail-max" => {
+                let v = source.value(name)?;
+                let len: u16 = v
+                    .parse()
+                    .map_err(|_| format!("invalid trail length '{}'", v))?;
+                let len = len.max(1);
+                if name == "trail-min" {
+                    self.trail_min = len;
+                } else {
+                    self.trail_max = len;
+                }
+            }
+            "speed-min" | "speed-max" => {
+                let v = source.value(name)?;
+                // 0 would leave `tick < speed` never true, freezing the drop
+                let speed: u8 = v
+                    .parse()
+                    .ok()
+                    .filter(|&n| n >= 1)
+                    .ok_or_else(|| format!("invalid speed '{}' (frames per step, >= 1)", v))?;
+                if name == "speed-min" {
+                    self.speed_min = speed;
+                } else {
+                    self.speed_max = speed;
+                }
+            }
+            "wind" => {
+                let v = source.value(name)?;
+                let wind: f32 = v
+                    .parse()
+                    .ok()
+                    .filter(|w: &f32| w.is_finite())
+                    .ok_or_else(|| format!("invalid wind '{}'", v))?;
+                self.wind = wind.clamp(-1.0, 1.0);
+            }
+            "seed" => {
+                let v = source.value(name)?;
+                let seed = v.parse().map_err(|_| format!("invalid seed '{}'", v))?;
+                self.seed = Some(seed);
+            }
+            "glitch-chance" | "glitch-rate" => {
+                let v = source.value(name)?;
+                let per_mille = parse_fraction(&v).ok_or_else(|| {
+                    format!("invalid {} '{}' (expected 0.0-1.0)", name, v)
+                })?;
+                if name == "glitch-chance" {
+                    self.glitch_chance = per_mille;
+                } else {
+                    self.glitch_rate = per_mille;
+                }
+            }
+            "write-chunk" => {
+                let v = source.value(name)?;
+                self.write_chunk = v
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| format!("invalid write chunk '{}' (bytes, > 0)", v))?;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Trail length bounds for a screen `rows` tall. Trails never outgrow
+    /// the screen, but the minimum wins on screens shorter than it.
+    fn trail_range(&self, rows: u16) -> (u16, u16) {
+        (self.trail_min, self.trail_max.min(rows).max(self.trail_min))
+    }
+
+    /// A random drop speed within `--speed-min`..=`--speed-max`.
+    fn roll_speed(&self, rng: &mut Rng) -> u8 {
+        rng.gen_range(self.speed_min as i32, self.speed_max as i32 + 1) as u8
+    }
+}
+
+/// Parse a `WxH` size such as `120x40`.
+fn parse_size(s: &str) -> Option<(u16, u16)> {
+    let (w, h) = s.split_once(['x', 'X'])?;
+    Some((w.trim().parse().ok()?, h.trim().parse().ok()?))
+}
+
+/// `line` up to a `#` that is not inside a quoted string.
+fn strip_toml_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (i, ch) in line.char_indices() {
+        match (quote, ch) {
+            (None, '"' | '\'') => quote = Some(ch),
+            (Some(q), _) if ch == q => quote = None,
+            (None, '#') => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Strip TOML quotes: `'literal'` as-is, `"basic"` with `\\` and `\"`
+/// unescaped. Bare values pass through.
+fn unquote_toml(value: &str) -> String {
+    let quoted = |q: char| {
+        value
+            .strip_prefix(q)
+            .and_then(|v| v.strip_suffix(q))
+            .filter(|_| value.len() >= 2)
+    };
+    if let Some(v) = quoted('\'') {
+        v.to_string()
+    } else if let Some(v) = quoted('"') {
+        v.replace("\\\\", "\\").replace("\\\"", "\"")
+    } else {
+        value.to_string()
+    }
+}
+
+/// Parse a probability in 0.0..=1.0 as per-mille.
+fn parse_fraction(s: &str) -> Option<u32> {
+    let p: f32 = s.trim().parse().ok()?;
+    (0.0..=1.0).contains(&p).then(|| (p * 1000.0).round() as u32)
+}
+
+/// Parse `N/D` with `D > 0` and `N <= D`.
+fn parse_ratio(s: &str) -> Option<(u32, u32)> {
+    let (n, d) = s.split_once('/')?;
+    let (n, d): (u32, u32) = (n.trim().parse().ok()?, d.trim().parse().ok()?);
+    (d > 0 && n <= d).then_some((n, d))
+}
+
+// ---------------------------------------------------------------------------
+// Application state
+// ---------------------------------------------------------------------------
+
+const MENU_INTRO_DURATION: Duration = Duration::from_millis(700);
+
+/// How long the idle dim takes to reach its floor, and how far it goes.
+const IDLE_DIM_FADE: Duration = Duration::from_secs(3);
+const IDLE_DIM_FLOOR: u8 = 96;
+
+/// Transient feedback after picking a payload.
+struct LaunchMessage {
+    text: String,
+    /// The launch failed; drawn in the error colour.
+    error: bool,
+    shown: Instant,
+}
+
+impl LaunchMessage {
+    /// Time left on screen out of `duration`; `None` once it has expired.
+    fn remaining(&self, duration: Duration) -> Option<Duration> {
+        duration
+            .checked_sub(self.shown.elapsed())
+            .filter(|left| !left.is_zero())
+    }
+
+    /// The message with a whole-second countdown, e.g. `Launched: x (2s)`.
+    fn countdown_text(&self, left: Duration) -> String {
+        format!("{} ({}s)", self.text, left.as_millis().div_ceil(1000))
+    }
+
+    fn attr(&self, palette: &AttrPalette) -> u16 {
+        if self.error {
+            ATTR_ERROR
+        } else {
+            palette.bright
+        }
+    }
+}
+
+pub struct App {
+    drops: Vec<Drop>,
+    frame_count: u64,
+    pub last_restart: Instant,
+    /// Last time `poll_input` returned anything but `None`.
+    pub last_input: Instant,
+    pub menu_open: bool,
+    /// Space toggles this; the rain holds still but the screen keeps rendering.
+    pub paused: bool,
+    /// `f` toggles an FPS readout in the top-left corner.
+    pub show_fps: bool,
+    /// Latest `FpsTracker::current_fps`, refreshed by the main loop.
+    pub current_fps: f64,
+    /// Local wall-clock (hours, minutes, seconds) for the status bar's
+    /// `time` field, refreshed by the main loop.
+    pub clock: Option<(u8, u8, u8)>,
+    pub menu: Menu,
+    menu_intro: Option<Instant>,
+    /// Entry waiting on the Y/N launch confirmation.
+    pub pending_launch: Option<(usize, usize)>,
+    launch_message: Option<LaunchMessage>,
+    palette: AttrPalette,
+    glyphs: GlyphSet,
+    /// Chance (numerator, denominator) a new glyph is katakana.
+    katakana_ratio: (u32, u32),
+    /// Multiplier on the extra drops; `resize` re-derives the count from it.
+    density: f32,
+    /// `--hot-columns` picked for the current width; empty spreads the
+    /// extra drops over every column.
+    hot_columns: Vec<u16>,
+    /// Fractional columns of wind not yet applied.
+    wind_drift: f32,
+    /// Settled glyphs along the bottom with `--pool`.
+    pool: Option<Pool>,
+    /// Launched payloads that have not exited yet, see `reap_payloads`.
+    running: Vec<Child>,
+    pub config: Config,
+    pub cols: u16,
+    pub rows: u16,
+    rng: Rng,
+}
+
+/// Screen cells per extra drop. At 30 rows this matches the old fixed
+/// `cols / 3` extras, and keeps the same look on taller or shorter windows.
+const CELLS_PER_EXTRA_DROP: usize = 90;
+
+/// Drops scattered over random columns on top of the one per column,
+/// scaled by the `--density` multiplier.
+fn extra_drop_count(cols: u16, rows: u16, density: f32) -> usize {
+    let extra = cols as usize * rows as usize / CELLS_PER_EXTRA_DROP;
+    (extra as f32 * density) as usize
+}
+
+/// `count` distinct random columns out of `cols` (all of them if fewer).
+fn pick_hot_columns(cols: u16, count: u16, rng: &mut Rng) -> Vec<u16> {
+    let mut all: Vec<u16> = (0..cols).collect();
+    let count = count.min(cols) as usize;
+    for i in 0..count {
+        let j = i + rng.gen_u32((all.len() - i) as u32) as usize;
+        all.swap(i, j);
+    }
+    all.truncate(count);
+    all
+}
+
+/// Column for an extra drop: one of the hot columns if there are any,
+/// otherwise anywhere.
+fn extra_drop_column(cols: u16, hot: &[u16], rng: &mut Rng) -> u16 {
+    if hot.is_empty() {
+        rng.gen_u32(cols as u32) as u16
+    } else {
+        hot[rng.gen_u32(hot.len() as u32) as usize]
+    }
+}
+
+/// One drop per column plus extras proportional to the screen area.
+fn seed_drops(
+    cols: u16,
+    rows: u16,
+    density: f32,
+    hot: &[u16],
+    config: &Config,
+    rng: &mut Rng,
+) -> Vec<Drop> {
+    let base = cols as usize;
+    let extra = extra_drop_count(cols, rows, density);
+    let mut drops = Vec::with_capacity(base + extra);
+    for c in 0..cols {
+        drops.push(Drop::new(c, rows, config, rng));
+    }
+    for _ in 0..extra {
+        let c = extra_drop_column(cols, hot, rng);
+        drops.push(Drop::new(c, rows, config, rng));
+    }
+    drops
+}
+
+/// The palette for the configured theme, colour mode and decay curve.
+fn palette_for(config: &Config) -> AttrPalette {
+    // Rainbow bands are 4-bit colours unless truecolor is on; the
+    // 256-colour ramps are per theme.
+    let color_mode = match config.color_mode {
+        ColorMode::Palette256 if config.rainbow => ColorMode::Ansi16,
+        mode => mode,
+    };
+    build_attr_palette(config.theme, color_mode, config.decay)
+}
+
+/// The configured `--seed`, or the clock when there is none.
+fn rng_for(config: &Config) -> Rng {
+    match config.seed {
+        Some(seed) => Rng::from_seed(seed),
+        None => Rng::new(),
+    }
+}
+
+impl App {
+    pub fn new(cols: u16, rows: u16, config: Config, glyphs: GlyphSet) -> Self {
+        let mut rng = rng_for(&config);
+        let hot_columns = pick_hot_columns(cols, config.hot_columns, &mut rng);
+        let drops = seed_drops(cols, rows, config.density, &hot_columns, &config, &mut rng);
+        Self {
+            drops,
+            frame_count: 0,
+            last_restart: Instant::now(),
+            last_input: Instant::now(),
+            menu_open: config.menu_first,
+            paused: false,
+            show_fps: false,
+            current_fps: 0.0,
+            clock: None,
+            menu: Menu::load(),
+            menu_intro: None,
+            pending_launch: None,
+            launch_message: None,
+            palette: palette_for(&config),
+            glyphs,
+            katakana_ratio: config.katakana_ratio,
+            density: config.density,
+            hot_columns,
+            wind_drift: 0.0,
+            pool: config.pool.then(|| Pool::new(cols)),
+            running: Vec::new(),
+            config,
+            cols,
+            rows,
+            rng,
+        }
+    }
+
+    pub fn resize(&mut self, new_cols: u16, new_rows: u16) {
+        self.cols = new_cols;
+        self.rows = new_rows;
+
+        for drop in &mut self.drops {
+            if drop.col >= new_cols {
+                drop.col = self.rng.gen_u32(new_cols as u32) as u16;
+                drop.reset(new_rows, &self.config, &mut self.rng);
+            }
+        }
+
+        // Hot columns are re-picked for the new width and the extras
+        // (everything past one per column) moved onto them
+        if self.config.hot_columns > 0 {
+            self.hot_columns = pick_hot_columns(new_cols, self.config.hot_columns, &mut self.rng);
+            for drop in self.drops.iter_mut().skip(new_cols as usize) {
+                drop.col = extra_drop_column(new_cols, &self.hot_columns, &mut self.rng);
+                drop.reset(new_rows, &self.config, &mut self.rng);
+            }
+        }
+
+        let target = new_cols as usize + extra_drop_count(new_cols, new_rows, self.density);
+        while self.drops.len() < target {
+            let c = extra_drop_column(new_cols, &self.hot_columns, &mut self.rng);
+            self.drops.push(Drop::new(c, new_rows, &self.config, &mut self.rng));
+        }
+        self.drops.truncate(target);
+
+        // Settled glyphs don't map onto the new bottom row; start over
+        if let Some(pool) = &mut self.pool {
+            *pool = Pool::new(new_cols);
+        }
+    }
+
+    /// Start the animation over with freshly rolled drops, keeping the
+    /// loaded menu, glyphs and settings. The caller forces a full repaint.
+    pub fn restart(&mut self) {
+        self.drops = seed_drops(
+            self.cols,
+            self.rows,
+            self.density,
+            &self.hot_columns,
+            &self.config,
+            &mut self.rng,
+        );
+        self.frame_count = 0;
+        self.last_restart = Instant::now();
+        if let Some(pool) = &mut self.pool {
+            *pool = Pool::new(self.cols);
+        }
+    }
+
+    /// Reroll every drop in place from a reseeded generator. With `--seed`
+    /// each reset yields the same fresh rain; the frame count, pool and
+    /// drop columns are left alone.
+    pub fn reset_drops(&mut self) {
+        self.rng = rng_for(&self.config);
+        for drop in &mut self.drops {
+            drop.reset(self.rows, &self.config, &mut self.rng);
+        }
+    }
+
+    /// Listed menu rows visible at once (the body minus the instructions
+    /// line and spacer), the step for PageUp/PageDown.
+    pub fn menu_page_rows(&self) -> usize {
+        let rect = MenuRect::for_screen(self.cols as usize, self.rows as usize);
+        rect.h.saturating_sub(4)
+    }
+
+    /// Enter on the menu cursor: toggle a category, or ask before
+    /// launching an entry.
+    pub fn activate_selection(&mut self) {
+        match self.menu.selected() {
+            Some(MenuIndex::Category(ci)) => {
+                let expanded = self.menu.categories[ci].expanded;
+                self.menu.set_expanded(ci, !expanded);
+            }
+            Some(MenuIndex::Entry(ci, ei)) => self.pending_launch = Some((ci, ei)),
+            None => {}
+        }
+    }
+
+    /// Forget payloads that have exited. Called every frame, even while the
+    /// rain is paused, so the status bar's running count stays current.
+    pub fn reap_payloads(&mut self) {
+        self.running.retain_mut(|child| match child.try_wait() {
+            Ok(status) => status.is_none(),
+            Err(e) => {
+                log_failure("wait for payload", &e);
+                false
+            }
+        });
+    }
+
+    /// Answer to the "Run <name>?" prompt.
+    pub fn resolve_pending_launch(&mut self, confirmed: bool) {
+        let Some((ci, ei)) = self.pending_launch.take() else {
+            return;
+        };
+        if !confirmed {
+            return;
+        }
+        let entry = &self.menu.categories[ci].entries[ei];
+        let launched = launch_payload(entry, self.config.exec_policy);
+        self.launch_message = Some(launch_outcome(entry, &launched));
+        if let Ok(child) = launched {
+            self.running.push(child);
+        }
+        if !self.config.keep_menu_open && !self.config.menu_first {
+            self.menu_open = false;
+        }
+    }
+
+    /// Switch to the next theme in `ColorTheme::ALL`. Cells already on
+    /// screen keep the old colours until the caller repaints them.
+    pub fn cycle_theme(&mut self) {
+        let themes = ColorTheme::ALL;
+        let current = themes.iter().position(|&t| t == self.config.theme).unwrap_or(0);
+        self.config.theme = themes[(current + 1) % themes.len()];
+        self.palette = palette_for(&self.config);
+    }
+
+    /// Left click at console (`x`, `y`) with the menu open: a row is
+    /// selected and activated, and a click outside the box closes the menu
+    /// unless it is the whole UI. While the launch prompt is up any click
+    /// declines it, so nothing changes behind the prompt.
+    pub fn click(&mut self, x: u16, y: u16) {
+        if self.pending_launch.is_some() {
+            self.resolve_pending_launch(false);
+            return;
+        }
+        let rect = MenuRect::for_screen(self.cols as usize, self.rows as usize);
+        let (r, c) = (y as usize, x as usize);
+        if !rect.contains(r, c) {
+            self.menu_open = self.config.menu_first;
+        } else if let Some(item) = self.menu.item_at(rect, r, c) {
+            self.menu.cursor = MenuIndex::from_item(item);
+            self.activate_selection();
+        }
+    }
+
+    pub fn open_menu(&mut self) {
+        self.menu_open = true;
+        if self.config.fancy_menu_open && self.menu_intro.is_none() {
+            self.menu_intro = Some(Instant::now());
+        }
+    }
+
+    /// Progress (0..1) of the menu intro animation while it is playing.
+    pub fn menu_intro_progress(&self) -> Option<f32> {
+        let started = self.menu_intro?;
+        let t = started.elapsed().as_secs_f32() / MENU_INTRO_DURATION.as_secs_f32();
+        (self.menu_open && t < 1.0).then_some(t)
+    }
+
+    /// Rain brightness (255 = full) once `--idle-dim-secs` pass without
+    /// input, easing down to `IDLE_DIM_FLOOR` over `IDLE_DIM_FADE`.
+    fn idle_brightness(&self) -> u8 {
+        let Some(after) = self.config.idle_dim_after else {
+            return 255;
+        };
+        let idle = self.last_input.elapsed().saturating_sub(after);
+        if idle.is_zero() {
+            return 255;
+        }
+        let t = (idle.as_secs_f32() / IDLE_DIM_FADE.as_secs_f32()).min(1.0);
+        255 - ((255 - IDLE_DIM_FLOOR) as f32 * t) as u8
+    }
+
+    pub fn update(&mut self) {
+        let screen = (self.cols, self.rows);
+        let rng = &mut self.rng;
+
+        // Wind builds up a fraction of a column per frame; each whole column
+        // is spent nudging every drop this frame.
+        self.wind_drift += self.config.wind;
+        let shift = self.wind_drift.trunc() as i32;
+        self.wind_drift -= shift as f32;
+
+        if self.config.rain_enabled {
+            let pool_frames = POOL_SECS * self.config.fps;
+            for drop in &mut self.drops {
+                let landed = drop.update(
+                    screen,
+                    shift,
+                    &self.config,
+                    &self.glyphs,
+                    self.katakana_ratio,
+                    rng,
+                );
+                if let Some(pool) = self.pool.as_mut().filter(|_| landed) {
+                    pool.settle(drop.col as usize, drop.trail_glyph(0), pool_frames);
+                }
+            }
+        }
+        if let Some(pool) = &mut self.pool {
+            pool.drain();
+        }
+        self.frame_count += 1;
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Dirty-cell tracking: bounds the diff to cells rendering actually touched
+// ---------------------------------------------------------------------------
+
+/// Indices of the cells `render_to_buffer` wrote this frame and last frame.
+/// Every non-blank cell is in `cur`, and every cell that may still hold last
+/// frame's content is in `prev`, so diffing their union is equivalent to
+/// diffing the whole grid while the rain is sparse.
+pub struct DirtyCells {
+    enabled: bool,
+    /// `prev` is unknown (startup, resize); clear and diff everything once.
+    stale: bool,
+    /// The current frame was cleared and must be diffed in full.
+    full_frame: bool,
+    cur: Vec<u32>,
+    prev: Vec<u32>,
+    candidates: Vec<u32>,
+    /// One bit per cell, used to merge `cur` and `prev` in index order
+    /// without sorting; all clear between calls to `candidates`.
+    seen: Vec<u64>,
+}
+
+impl DirtyCells {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            stale: true,
+            full_frame: true,
+            cur: Vec::new(),
+            prev: Vec::new(),
+            candidates: Vec::new(),
+            seen: Vec::new(),
+        }
+    }
+
+    pub fn invalidate(&mut self) {
+        self.stale = true;
+    }
+
+    /// Blank the cells written last frame (or the whole grid if untracked).
+    fn begin_frame(&mut self, buf: &mut [Cell]) {
+        std::mem::swap(&mut self.cur, &mut self.prev);
+        self.cur.clear();
+        self.full_frame = !self.enabled || self.stale;
+        self.stale = false;
+        if self.full_frame {
+            buf.fill(Cell::BLANK);
+        } else {
+            for &idx in &self.prev {
+                buf[idx as usize] = Cell::BLANK;
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn mark(&mut self, idx: usize) {
+        if self.enabled {
+            self.cur.push(idx as u32);
+        }
+    }
+
+    /// Sorted union of this frame's and last frame's cells, or `None` when
+    /// the whole grid has to be diffed.
+    pub fn candidates(&mut self) -> Option<&[u32]> {
+        if self.full_frame {
+            return None;
+        }
+        self.candidates.clear();
+        for &idx in self.cur.iter().chain(&self.prev) {
+            let word = idx as usize / 64;
+            if word >= self.seen.len() {
+                self.seen.resize(word + 1, 0);
+            }
+            self.seen[word] |= 1 << (idx % 64);
+        }
+        // Walking the bitmap is a pass over cells / 64 words, far cheaper
+        // than sorting the marks once the rain covers much of the screen
+        for (word, bits) in self.seen.iter_mut().enumerate() {
+            while *bits != 0 {
+                let bit = bits.trailing_zeros();
+                self.candidates.push((word * 64) as u32 + bit);
+                *bits &= *bits - 1;
+            }
+        }
+        Some(&self.candidates)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Rendering into Cell buffer
+// ---------------------------------------------------------------------------
+
+/// Draw every drop's visible trail. While the menu is fully open, cells under
+/// it are skipped (the overlay would paint over them anyway) unless
+/// `--rain-behind-menu` is set.
+fn render_drops(buf: &mut [Cell], dirty: &mut DirtyCells, app: &App) {
+    let cols = app.cols as usize;
+    let rows = app.rows as usize;
+    let palette = &app.palette;
+    let menu_rect = MenuRect::for_screen(cols, rows);
+    let intro = app.menu_intro_progress();
+    let occluded = app.menu_open && intro.is_none() && !app.config.rain_behind_menu;
+    let idle = app.idle_brightness();
+    let band_shift = (app.frame_count / RAINBOW_SCROLL_FRAMES) as usize;
+    let head_len = app.config.head_len;
+
+    for drop in &app.drops {
+        let c = drop.col as usize;
+        if c >= cols || drop.len == 0 {
+            continue;
+        }
+
+        let head_row = drop.head;
+        let len = drop.len as i32;
+        let tail_row = head_row - len + 1;
+
+        let vis_top = tail_row.max(0);
+        let vis_bot = head_row.min(rows as i32 - 1);
+        if vis_top > vis_bot {
+            continue;
+        }
+
+        let i_start = (head_row - vis_bot) as u16;
+        let i_end = (head_row - vis_top) as u16;
+
+        for i in i_start..=i_end {
+            let r = app.config.direction.screen_row((head_row - i as i32) as usize, rows);
+            if occluded && menu_rect.contains(r, c) {
+                continue;
+            }
+            let glyph = drop.trail_glyph(i);
+
+            // Distance from the bright end; --invert-fade puts it at the tail.
+            let level = if app.config.invert_fade {
+                drop.len - 1 - i
+            } else {
+                i
+            };
+            let attr = if idle < 255 && (level < head_len || drop.streak) {
+                // Idle: no bright heads, the whole drop fades as trail.
+                palette.trail_attr(0, 1)
+            } else if level == 0 || drop.streak {
+                palette.head
+            } else if level < head_len {
+                palette.near_head_attr(level as usize - 1, head_len as usize - 1)
+            } else {
+                let max_trail = drop.max_len.saturating_sub(head_len).max(1) as usize;
+                palette.trail_attr((level - head_len) as usize, max_trail)
+            };
+            let brightness = (drop.brightness as u32 * idle as u32 / 255) as u8;
+            let attr = if brightness < 255 {
+                palette.scale_attr(attr, brightness)
+            } else {
+                attr
+            };
+            let attr = if app.config.rainbow {
+                palette.rainbow_attr(attr, c / RAINBOW_BAND_COLS + band_shift)
+            } else {
+                attr
+            };
+            let attr = if app.config.bold_head && idle == 255 && (level == 0 || drop.streak) {
+                attr | ATTR_BOLD
+            } else {
+                attr
+            };
+
+            let c = match intro {
+                Some(t) => push_aside(r, c, menu_rect, t, cols),
+                None => c,
+            };
+            // A wide glyph also covers the next cell, which holds the low
+            // half of a surrogate pair; blank it where there is no room for
+            // its right half.
+            let wide = glyph.width() == 2;
+            let fits = c + 1 < cols && !(occluded && menu_rect.contains(r, c + 1));
+            let ch = if wide && !fits { b' ' as u16 } else { glyph.unit };
+            let idx = r * cols + c;
+            buf[idx] = Cell { ch, attr };
+            dirty.mark(idx);
+            if wide && fits {
+                buf[idx + 1] = match glyph.low {
+                    Some(low) => Cell { ch: low, attr },
+                    None => Cell::WIDE_TAIL,
+                };
+                dirty.mark(idx + 1);
+            }
+        }
+    }
+}
+
+/// Overlay the `--pool` glyphs on the bottom rows (top ones for rising
+/// rain) in the dim theme colour, skipping the open menu like `render_drops`.
+fn render_pool(buf: &mut [Cell], dirty: &mut DirtyCells, pool: &Pool, app: &App) {
+    let cols = app.cols as usize;
+    let rows = app.rows as usize;
+    let menu_rect = MenuRect::for_screen(cols, rows);
+    let occluded = app.menu_open && !app.config.rain_behind_menu;
+    for (depth, c, glyph) in pool.settled() {
+        if depth >= rows || c >= cols || glyph.width() == 2 {
+            continue;
+        }
+        let r = app.config.direction.screen_row(rows - 1 - depth, rows);
+        if occluded && menu_rect.contains(r, c) {
+            continue;
+        }
+        buf[r * cols + c] = Cell { ch: glyph.unit, attr: app.palette.dim };
+        dirty.mark(r * cols + c);
+    }
+}
+
+/// How long a `--message` reveal lasts, and the share of it spent
+/// resolving out of (and back into) the rain at each end.
+const HIDDEN_MESSAGE_SECS: f32 = 4.0;
+const HIDDEN_MESSAGE_FADE: f32 = 0.25;
+
+/// Draw the `--message` text centred on the middle row while a reveal is
+/// running. Letters appear and vanish one by one in a fixed scattered
+/// order; a message wider than the screen keeps its centre and loses both
+/// ends.
+fn render_hidden_message(buf: &mut [Cell], dirty: &mut DirtyCells, text: &str, app: &App) {
+    let cols = app.cols as usize;
+    let rows = app.rows as usize;
+    let fps = app.config.fps as f32;
+    let period = (app.config.hidden_message_every.as_secs_f32() * fps) as u64;
+    let shown = (HIDDEN_MESSAGE_SECS * fps) as u64;
+    // Each reveal ends on an interval boundary, so the first one finishes
+    // one interval in rather than starting on the very first frame
+    let start = period.saturating_sub(shown);
+    let phase = app.frame_count % period.max(1);
+    if phase < start {
+        return;
+    }
+    let t = (phase - start) as f32 / shown.max(1) as f32;
+    let visible = (t.min(1.0 - t) / HIDDEN_MESSAGE_FADE).min(1.0);
+
+    let chars: Vec<char> = text.chars().collect();
+    let skip = chars.len().saturating_sub(cols) / 2;
+    let width = chars.len().min(cols);
+    let left = (cols - width) / 2;
+    let r = rows / 2;
+    for (i, &ch) in chars.iter().enumerate().skip(skip).take(width) {
+        // Scattered per-letter threshold in 0..1
+        let threshold = (i * 37 % 64) as f32 / 64.0;
+        if ch == ' ' || threshold >= visible {
+            continue;
+        }
+        let glyph = Glyph::from_char(ch);
+        let unit = if glyph.width() == 1 && glyph.low.is_none() {
+            glyph.unit
+        } else {
+            b'?' as u16
+        };
+        let idx = r * cols + left + i - skip;
+        buf[idx] = Cell { ch: unit, attr: app.palette.head };
+        dirty.mark(idx);
+    }
+}
+
+/// One `--status-fields` entry.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum StatusField {
+    Frame,
+    Fps,
+    Drops,
+    Time,
+    /// Menu and quit key hints, left out while the menu is open.
+    Keys,
+}
+
+impl StatusField {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "frame" => Some(StatusField::Frame),
+            "fps" => Some(StatusField::Fps),
+            "drops" => Some(StatusField::Drops),
+            "time" => Some(StatusField::Time),
+            "keys" => Some(StatusField::Keys),
+            _ => None,
+        }
+    }
+
+    /// The field's status bar text, if it has anything to show right now.
+    fn text(self, app: &App) -> Option<String> {
+        match self {
+            StatusField::Frame => Some(format!("frame {}", app.frame_count)),
+            StatusField::Fps => Some(format!("{:.1} fps", app.current_fps)),
+            StatusField::Drops => Some(format!("{} drops", app.drops.len())),
+            StatusField::Time => {
+                let (h, m, s) = app.clock?;
+                Some(format!("{:02}:{:02}:{:02}", h, m, s))
+            }
+            StatusField::Keys if app.menu_open => None,
+            StatusField::Keys => {
+                let keys = &app.config.keys;
+                Some(format!(
+                    "{} for menu // {} to quit",
+                    keys.menu[0].label(),
+                    keys.quit[0].label()
+                ))
+            }
+        }
+    }
+}
+
+/// Height of the `--flicker` band.
+const FLICKER_BAND_ROWS: usize = 3;
+
+/// How long the status bar stays at one anchor before moving along.
+const STATUS_SHIFT_SECS: u64 = 180;
+
+pub fn render_to_buffer(buf: &mut [Cell], app: &App, dirty: &mut DirtyCells) {
+    let cols = app.cols as usize;
+    let rows = app.rows as usize;
+    let total = cols * rows;
+
+    dirty.begin_frame(&mut buf[..total]);
+    if total == 0 {
+        return;
+    }
+
+    let (min_cols, min_rows) = app.config.min_size;
+    if app.cols < min_cols || app.rows < min_rows {
+        let msg = format!("Terminal too small (need {}x{})", min_cols, min_rows);
+        draw_centered_text(buf, dirty, rows / 2, &msg, app.palette.bright, cols);
+        return;
+    }
+
+    let menu_rect = MenuRect::for_screen(cols, rows);
+    let intro = app.menu_intro_progress();
+
+    if app.config.rain_enabled {
+        render_drops(buf, dirty, app);
+    }
+    if let Some(pool) = &app.pool {
+        render_pool(buf, dirty, pool, app);
+    }
+    if let Some(text) = &app.config.hidden_message {
+        render_hidden_message(buf, dirty, text, app);
+    }
+
+    // Scanlines: dim the rain on odd rows
+    if app.config.scanlines {
+        for row in buf[..total].chunks_exact_mut(cols).skip(1).step_by(2) {
+            for cell in row {
+                cell.attr = app.palette.dim_attr(cell.attr);
+            }
+        }
+    }
+
+    // Flicker: a band one step dimmer moves down a row per frame, entering
+    // above the top edge and leaving below the bottom before it repeats.
+    // Drawn before the status bar and menu so those stay steady.
+    if app.config.flicker {
+        let pos = (app.frame_count % (rows + FLICKER_BAND_ROWS) as u64) as usize;
+        let band = pos.saturating_sub(FLICKER_BAND_ROWS)..pos.min(rows);
+        for cell in &mut buf[band.start * cols..band.end * cols] {
+            cell.attr = app.palette.dim_attr(cell.attr);
+        }
+    }
+
+    // Status bar (--no-status-bar leaves the bottom row to the rain)
+    let mut parts = vec!["BADDERBLOOD".to_string()];
+    parts.extend(app.config.status_fields.iter().filter_map(|f| f.text(app)));
+    if !app.running.is_empty() {
+        parts.push(format!("running: {}", app.running.len()));
+    }
+    if app.paused {
+        parts.push("PAUSED".to_string());
+    }
+    let status = format!(" {} ", parts.join(" // "));
+    let sw = text_width(&status);
+    if app.config.show_status && cols > sw + 2 && rows > 1 {
+        // Step right -> center -> left so a long run doesn't burn the
+        // same cells into OLED / plasma panels
+        let shift_frames = STATUS_SHIFT_SECS * app.config.fps as u64;
+        let sx = match app.frame_count / shift_frames % 3 {
+            0 => cols - sw - 1,
+            1 => (cols - sw) / 2,
+            _ => 1,
+        };
+        let sy = rows.saturating_sub(1);
+        draw_text(buf, dirty, sy, sx..cols, &status, app.palette.dim, cols);
+    }
+
+    // FPS readout
+    if app.show_fps {
+        let readout = format!(" {:.1} fps ", app.current_fps);
+        for (i, &b) in readout.as_bytes().iter().take(cols).enumerate() {
+            buf[i].ch = b as u16;
+            buf[i].attr = app.palette.dim;
+            dirty.mark(i);
+        }
+    }
+
+    // Launch message (drawn inside the menu frame instead while it is open)
+    let message_duration = app.config.message_duration;
+    if let Some(msg) = app.launch_message.as_ref().filter(|_| !app.menu_open) {
+        if let Some(left) = msg.remaining(message_duration) {
+            let display = format!(" {} ", msg.countdown_text(left));
+            let attr = msg.attr(&app.palette);
+            let mw = text_width(&display);
+            if cols > mw + 2 && rows > 2 {
+                let mx = cols.saturating_sub(mw + 1);
+                let my = rows.saturating_sub(2);
+                draw_text(buf, dirty, my, mx..cols, &display, attr, cols);
+            }
+        }
+    }
+
+    // Menu overlay
+    if let Some(t) = intro {
+        render_menu_intro(buf, dirty, &app.palette, menu_rect, t, cols, rows);
+    } else if app.menu_open {
+        let message = app.launch_message.as_ref().and_then(|msg| {
+            let left = msg.remaining(message_duration)?;
+            Some((msg.countdown_text(left), msg.attr(&app.palette)))
+        });
+        let message = message.as_ref().map(|(text, attr)| (text.as_str(), *attr));
+        render_menu_to_buffer(buf, dirty, &app.menu, &app.palette, message, cols, rows);
+        if let Some((ci, ei)) = app.pending_launch {
+            let name = &app.menu.categories[ci].entries[ei].name;
+            let prompt = format!("Run {}? [Y/N]", name);
+            render_prompt(buf, dirty, &prompt, app.palette.bright, cols, rows);
+        }
+    }
+}
+
+/// Draw `text` in a bordered box in the middle of the screen.
+fn render_prompt(
+    buf: &mut [Cell],
+    dirty: &mut DirtyCells,
+    text: &str,
+    attr: u16,
+    cols: usize,
+    rows: usize,
+) {
+    let rule = format!("+{}+", "-".repeat(text_width(text) + 2));
+    let row = rows / 2;
+    draw_centered_text(buf, dirty, row.saturating_sub(1), &rule, attr, cols);
+    draw_centered_text(buf, dirty, row, &format!("| {} |", text), attr, cols);
+    draw_centered_text(buf, dirty, row + 1, &rule, attr, cols);
+}
+
+/// Draw `text` horizontally centred on `row`, clipped to the screen width.
+fn draw_centered_text(
+    buf: &mut [Cell],
+    dirty: &mut DirtyCells,
+    row: usize,
+    text: &str,
+    attr: u16,
+    cols: usize,
+) {
+    let shown = text_width(text).min(cols);
+    let start = (cols - shown) / 2;
+    draw_text(buf, dirty, row, start..cols, text, attr, cols);
+}
+
+/// Screen rectangle occupied by the menu overlay.
+#[derive(Clone, Copy)]
+pub struct MenuRect {
+    x: usize,
+    y: usize,
+    w: usize,
+    h: usize,
+}
+
+impl MenuRect {
+    pub fn for_screen(cols: usize, rows: usize) -> Self {
+        let w = 80usize.min(cols.saturating_sub(4));
+        let h = (rows * 2 / 3).max(16).min(rows.saturating_sub(4));
+        Self {
+            x: (cols.saturating_sub(w)) / 2,
+            y: (rows.saturating_sub(h)) / 2,
+            w,
+            h,
+        }
+    }
+
+    pub fn contains(&self, r: usize, c: usize) -> bool {
+        r >= self.y && r < self.y + self.h && c >= self.x && c < self.x + self.w
+    }
+}
+
+/// Column a rain cell is drawn at while the menu intro pushes it out of
+/// `rect`: cells inside slide toward the nearer side edge as `t` goes 0 -> 1.
+fn push_aside(r: usize, c: usize, rect: MenuRect, t: f32, cols: usize) -> usize {
+    if !rect.contains(r, c) {
+        return c;
+    }
+    let target = if c < rect.x + rect.w / 2 {
+        rect.x as f32 - 1.0
+    } else {
+        (rect.x + rect.w) as f32
+    };
+    let pushed = c as f32 + (target - c as f32) * t;
+    (pushed.round().max(0.0) as usize).min(cols - 1)
+}
+
+/// Empty menu frame growing from the centre of `rect` (intro animation).
+fn render_menu_intro(
+    buf: &mut [Cell],
+    dirty: &mut DirtyCells,
+    palette: &AttrPalette,
+    rect: MenuRect,
+    t: f32,
+    cols: usize,
+    rows: usize,
+) {
+    let w = ((rect.w as f32 * t) as usize).max(2).min(rect.w);
+    let h = ((rect.h as f32 * t) as usize).max(2).min(rect.h);
+    let x0 = rect.x + rect.w.saturating_sub(w) / 2;
+    let y0 = rect.y + rect.h.saturating_sub(h) / 2;
+
+    for r in y0..(y0 + h).min(rows) {
+        for c in x0..(x0 + w).min(cols) {
+            let edge_r = r == y0 || r == y0 + h - 1;
+            let edge_c = c == x0 || c == x0 + w - 1;
+            let ch = match (edge_r, edge_c) {
+                (true, true) => b'+',
+                (true, false) => b'-',
+                (false, true) => b'|',
+                (false, false) => b' ',
+            };
+            let cell = &mut buf[r * cols + c];
+            cell.ch = ch as u16;
+            cell.attr = if ch == b' ' { ATTR_BLACK } else { palette.bright };
+            dirty.mark(r * cols + c);
+        }
+    }
+}
+
+/// Console cells `text` takes when drawn with `draw_text`.
+fn text_width(text: &str) -> usize {
+    text.chars().map(|ch| Glyph::from_char(ch).width() as usize).sum()
+}
+
+/// Longest prefix of `text` that fits in `width` cells.
+fn take_width(text: &str, width: usize) -> &str {
+    let mut used = 0;
+    for (i, ch) in text.char_indices() {
+        used += Glyph::from_char(ch).width() as usize;
+        if used > width {
+            return &text[..i];
+        }
+    }
+    text
+}
+
+/// Draw `text` on `row` from the start of `span`, one cell per glyph and
+/// two for wide ones, stopping before a glyph that would leave `span`.
+fn draw_text(
+    buf: &mut [Cell],
+    dirty: &mut DirtyCells,
+    row: usize,
+    span: std::ops::Range<usize>,
+    text: &str,
+    attr: u16,
+    cols: usize,
+) {
+    if (row + 1) * cols > buf.len() {
+        return;
+    }
+    let end = span.end.min(cols);
+    let mut c = span.start;
+    for ch in text.chars() {
+        let glyph = Glyph::from_char(ch);
+        let w = glyph.width() as usize;
+        if c + w > end {
+            break;
+        }
+        let idx = row * cols + c;
+        buf[idx] = Cell { ch: glyph.unit, attr };
+        dirty.mark(idx);
+        if w == 2 {
+            buf[idx + 1] = match glyph.low {
+                Some(low) => Cell { ch: low, attr },
+                None => Cell::WIDE_TAIL,
+            };
+            dirty.mark(idx + 1);
+        }
+        c += w;
+    }
+}
+
+/// `name` cut to `width` cells with `...` before the extension, so
+/// `averyverylongname.ps1` reads `averyver...ps1` and the script kind stays
+/// visible. Names that fit are returned as they are.
+fn ellipsize(name: &str, width: usize) -> String {
+    const DOTS: &str = "...";
+    if text_width(name) <= width {
+        return name.to_string();
+    }
+    if width <= DOTS.len() {
+        return take_width(name, width).to_string();
+    }
+    let ext = match name.rfind('.') {
+        Some(dot) if dot > 0 => &name[dot + 1..],
+        _ => "",
+    };
+    let ext_width = text_width(ext);
+    // Keep at least one leading cell; otherwise drop the extension
+    let (head, tail) = if width > DOTS.len() + ext_width {
+        (width - DOTS.len() - ext_width, ext)
+    } else {
+        (width - DOTS.len(), "")
+    };
+    let mut out = take_width(name, head).to_string();
+    out.push_str(DOTS);
+    out.push_str(tail);
+    out
+}
+
+fn render_menu_to_buffer(
+    buf: &mut [Cell],
+    dirty: &mut DirtyCells,
+    menu: &Menu,
+    palette: &AttrPalette,
+    message: Option<(&str, u16)>,
+    cols: usize,
+    rows: usize,
+) {
+    let MenuRect {
+        x: mx,
+        y: my,
+        w: menu_width,
+        h: menu_height,
+    } = MenuRect::for_screen(cols, rows);
+    // Too small for even a bordered box
+    if menu_width < 3 || menu_height < 3 {
+        return;
+    }
+
+    let border_attr = palette.bright;
+    let title_attr = palette.bright;
+    let instr_attr = palette.dim;
+    let cat_sel_attr = palette.selected;
+    let entry_attr = palette.dim;
+    let entry_sel_attr = palette.selected;
+    let bg_attr: u16 = 0x00;
+
+    // Clear menu area
+    for r in my..my + menu_height {
+        for c in mx..mx + menu_width {
+            if r < rows && c < cols {
+                let cell = &mut buf[r * cols + c];
+                cell.ch = b' ' as u16;
+                cell.attr = bg_attr;
+                dirty.mark(r * cols + c);
+            }
+        }
+    }
+
+    let draw_char = |buf: &mut [Cell],
+                     dirty: &mut DirtyCells,
+                     r: usize,
+                     c: usize,
+                     ch: u16,
+                     attr: u16| {
+        if r < rows && c < cols {
+            let cell = &mut buf[r * cols + c];
+            cell.ch = ch;
+            cell.attr = attr;
+            dirty.mark(r * cols + c);
+        }
+    };
+
+    // Top/bottom borders
+    for c in mx..mx + menu_width {
+        draw_char(buf, dirty, my, c, b'-' as u16, border_attr);
+        draw_char(buf, dirty, my + menu_height - 1, c, b'-' as u16, border_attr);
+    }
+    for r in my..my + menu_height {
+        draw_char(buf, dirty, r, mx, b'|' as u16, border_attr);
+        draw_char(buf, dirty, r, mx + menu_width - 1, b'|' as u16, border_attr);
+    }
+    draw_char(buf, dirty, my, mx, b'+' as u16, border_attr);
+    draw_char(buf, dirty, my, mx + menu_width - 1, b'+' as u16, border_attr);
+    draw_char(buf, dirty, my + menu_height - 1, mx, b'+' as u16, border_attr);
+    draw_char(buf, dirty, my + menu_height - 1, mx + menu_width - 1, b'+' as u16, border_attr);
+
+    // Title
+    let title = " BadderBlood // Payload Launcher ";
+    let title_start = mx + 2;
+    let title_end = mx + menu_width - 1;
+    draw_text(buf, dirty, my, title_start..title_end, title, title_attr, cols);
+
+    // Launch confirmation on the bottom border
+    if let Some((msg, msg_attr)) = message {
+        let display = format!(" {} ", msg);
+        let row = my + menu_height - 1;
+        draw_text(buf, dirty, row, title_start..title_end, &display, msg_attr, cols);
+    }
+
+    let inner_x = mx + 1;
+    let inner_y = my + 1;
+    let inner_w = menu_width.saturating_sub(2);
+    let inner_h = menu_height.saturating_sub(2);
+
+    let mut lines: Vec<(String, u16)> = Vec::new();
+
+    let instructions = if menu.filtering {
+        format!(" Filter: /{}_  [Enter] Done  [Esc] Clear", menu.filter)
+    } else if !menu.filter.is_empty() {
+        format!(" Filter: {}  [/] Edit  [Esc] Clear", menu.filter)
+    } else {
+        " [Up/Dn] Navigate  [Enter] Select  [L/R] Fold  [/] Filter  [Esc] Close".to_string()
+    };
+    lines.push((instructions, instr_attr));
+    lines.push((String::new(), bg_attr));
+
+    let items = menu.items();
+    if menu.categories.is_empty() {
+        // Onboarding hint: where we looked and the layout we expect there
+        let why = if menu.payload_dir_found {
+            " No payloads found in:"
+        } else {
+            " Payload folder not found:"
+        };
+        let example = menu.payload_dir.join("<category>").join("script.ps1");
+        lines.push((why.to_string(), instr_attr));
+        lines.push((format!("   {}", menu.payload_dir.display()), ATTR_ERROR));
+        lines.push((String::new(), bg_attr));
+        lines.push((" Create one folder per category, e.g.".to_string(), instr_attr));
+        lines.push((format!("   {}", example.display()), instr_attr));
+        let kinds = " Scripts may be .ps1, .cmd or .bat; restart to rescan.";
+        lines.push((kinds.to_string(), instr_attr));
+    } else if items.is_empty() {
+        lines.push((" No payloads match the filter".to_string(), ATTR_ERROR));
+    } else {
+        for &(is_cat, ci, ei) in items {
+            let selected = menu.cursor_is((is_cat, ci, ei));
+            let cat = &menu.categories[ci];
+            if is_cat {
+                let prefix = if cat.expanded { "v " } else { "> " };
+                let attr = if selected { cat_sel_attr } else { palette.category_attr(ci) };
+                lines.push((format!(" {}{}", prefix, cat.label), attr));
+            } else {
+                let attr = if selected { entry_sel_attr } else { entry_attr };
+                // Indent and trailing space around the name
+                let name_w = inner_w.saturating_sub(6);
+                let name = ellipsize(&cat.entries[ei].name, name_w);
+                lines.push((format!("     {} ", name), attr));
+            }
+        }
+    }
+
+    let visible_height = inner_h;
+    let scroll = menu.scroll_for(visible_height);
+
+    for (li, (text, attr)) in lines.iter().enumerate().skip(scroll).take(visible_height) {
+        let row = inner_y + (li - scroll);
+        if row >= rows {
+            break;
+        }
+        draw_text(buf, dirty, row, inner_x..inner_x + inner_w, text, *attr, cols);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// VT-based diff renderer: only emit escape sequences for changed cells
+// ---------------------------------------------------------------------------
+
+/// VT output buffer, reused across frames. The caller writes each frame's
+/// bytes to the console. Glyphs are UTF-16 code units encoded to UTF-8 (the
+/// console runs in CP 65001).
+pub struct VtRenderer {
+    out: Vec<u8>,
+    /// `--transparent`: black backgrounds become the terminal default.
+    transparent: bool,
+}
+
+impl VtRenderer {
+    pub fn new(capacity: usize, transparent: bool) -> Self {
+        Self {
+            out: Vec::with_capacity(capacity),
+            transparent,
+        }
+    }
+
+    /// Compare `cur` against `prev`, emit VT sequences for differences,
+    /// then copy cur -> prev. Returns the bytes to write (empty if nothing
+    /// changed).
+    ///
+    /// With `candidates` only those cell indices (sorted) are compared;
+    /// otherwise the whole grid is walked.
+    pub fn render_diff(
+        &mut self,
+        cur: &[Cell],
+        prev: &mut [Cell],
+        cols: usize,
+        rows: usize,
+        candidates: Option<&[u32]>,
+    ) -> &[u8] {
+        self.out.clear();
+        self.emit_diff(cur, prev, cols, rows, candidates)
+    }
+
+    /// Append the changed cells to whatever `out` already holds.
+    fn emit_diff(
+        &mut self,
+        cur: &[Cell],
+        prev: &mut [Cell],
+        cols: usize,
+        rows: usize,
+        candidates: Option<&[u32]>,
+    ) -> &[u8] {
+        match candidates {
+            Some(list) => self.diff_cells(list.iter().map(|&i| i as usize), cur, prev, cols),
+            None => self.diff_cells(0..cols * rows, cur, prev, cols),
+        }
+
+        if !self.out.is_empty() {
+            self.out.extend_from_slice(b"\x1b[0m");
+        }
+        &self.out
+    }
+
+    fn diff_cells<I: Iterator<Item = usize>>(
+        &mut self,
+        indices: I,
+        cur: &[Cell],
+        prev: &mut [Cell],
+        cols: usize,
+    ) {
+        let mut last_attr: u16 = 0xFFFF;
+        let mut bold = false;
+        let mut cursor_row: usize = usize::MAX;
+        let mut cursor_col: usize = usize::MAX;
+
+        for idx in indices {
+            let c = cur[idx];
+            let p = prev[idx];
+            if c == p {
+                continue;
+            }
+            prev[idx] = c;
+            if c == Cell::WIDE_TAIL {
+                continue;
+            }
+
+            // A surrogate pair is written as one character from its first
+            // cell, whichever half changed.
+            let idx = if is_low_surrogate(c.ch)
+                && idx % cols > 0
+                && is_high_surrogate(cur[idx - 1].ch)
+            {
+                idx - 1
+            } else {
+                idx
+            };
+            let c = cur[idx];
+            prev[idx] = c;
+            let r = idx / cols;
+            let col = idx % cols;
+
+            if r != cursor_row || col != cursor_col {
+                write_cursor_pos(&mut self.out, r + 1, col + 1);
+            }
+
+            if c.attr != last_attr {
+                if bold && c.attr & ATTR_BOLD == 0 {
+                    self.out.extend_from_slice(b"\x1b[22m");
+                }
+                write_sgr(&mut self.out, c.attr, self.transparent);
+                last_attr = c.attr;
+                bold = c.attr & ATTR_BOLD != 0;
+            }
+
+            cursor_row = r;
+            let low = cur.get(idx + 1).filter(|_| col + 1 < cols).map(|n| n.ch);
+            match low {
+                Some(low) if is_high_surrogate(c.ch) && is_low_surrogate(low) => {
+                    prev[idx + 1] = cur[idx + 1];
+                    write_utf16_pair(&mut self.out, c.ch, low);
+                    cursor_col = col + 2;
+                }
+                _ => {
+                    write_utf16_unit(&mut self.out, c.ch);
+                    cursor_col = col + char_width(c.ch) as usize;
+                }
+            }
+        }
+    }
+
+    /// Full repaint: mark entire prev buffer as dirty then render_diff.
+    pub fn render_full(
+        &mut self,
+        cur: &[Cell],
+        prev: &mut [Cell],
+        cols: usize,
+        rows: usize,
+    ) -> &[u8] {
+        let sentinel = Cell { ch: 0xFFFF, attr: 0xFFFF };
+        for p in prev.iter_mut() {
+            *p = sentinel;
+        }
+        // Re-hide the cursor too: a launched payload or console redraw may
+        // have shown it since startup.
+        self.out.clear();
+        self.out.extend_from_slice(b"\x1b[?25l");
+        self.emit_diff(cur, prev, cols, rows, None)
+    }
+}
+
+/// OSC 0 sequence setting the window title. Control characters are dropped
+/// so a BEL or ESC in `--title` can't end the sequence early.
+pub fn title_sequence(title: &str) -> Vec<u8> {
+    let text: String = title.chars().filter(|c| !c.is_control()).collect();
+    format!("\x1b]0;{}\x07", text).into_bytes()
+}
+
+#[inline]
+fn write_utf16_unit(buf: &mut Vec<u8>, ch: u16) {
+    if ch < 0x80 {
+        buf.push(ch as u8);
+    } else {
+        let c = char::from_u32(ch as u32).unwrap_or('?');
+        buf.extend_from_slice(c.encode_utf8(&mut [0u8; 4]).as_bytes());
+    }
+}
+
+/// UTF-8 for a surrogate pair split across two cells.
+fn write_utf16_pair(buf: &mut Vec<u8>, high: u16, low: u16) {
+    let c = char::decode_utf16([high, low])
+        .next()
+        .and_then(Result::ok)
+        .unwrap_or('?');
+    buf.extend_from_slice(c.encode_utf8(&mut [0u8; 4]).as_bytes());
+}
+
+#[inline]
+fn write_cursor_pos(buf: &mut Vec<u8>, row: usize, col: usize) {
+    buf.extend_from_slice(b"\x1b[");
+    write_usize(buf, row);
+    buf.push(b';');
+    write_usize(buf, col);
+    buf.push(b'H');
+}
+
+#[inline]
+fn write_usize(buf: &mut Vec<u8>, n: usize) {
+    if n < 10 {
+        buf.push(b'0' + n as u8);
+    } else if n < 100 {
+        buf.push(b'0' + (n / 10) as u8);
+        buf.push(b'0' + (n % 10) as u8);
+    } else if n < 1000 {
+        buf.push(b'0' + (n / 100) as u8);
+        buf.push(b'0' + ((n / 10) % 10) as u8);
+        buf.push(b'0' + (n % 10) as u8);
+    } else {
+        let s = n.to_string();
+        buf.extend_from_slice(s.as_bytes());
+    }
+}
+
+// ---------------------------------------------------------------------------
+// FPS tracking
+// ---------------------------------------------------------------------------
+
+/// How `FpsTracker` lays out each log line.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FpsFormat {
+    /// `key=value` pairs separated by spaces.
+    Text,
+    /// One JSON object per line, for monitoring tools.
+    Json,
+}
+
+impl FpsFormat {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "text" => Some(FpsFormat::Text),
+            "json" => Some(FpsFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+pub struct FpsTracker {
+    window_frames: u64,
+    total_frames: u64,
+    window_start: Instant,
+    /// Latest sample only, replaced atomically so pollers never see a
+    /// partial line.
+    fps_file_path: PathBuf,
+    /// Every sample, appended.
+    history_file_path: PathBuf,
+    /// Frames in the current one-second window behind `current_fps`.
+    second_frames: u32,
+    second_start: Instant,
+    last_second_fps: f64,
+    /// Configured frame rate, logged alongside the measured one.
+    target_fps: u32,
+    /// `--profile`: per-phase time summed over the current log window.
+    phases: Option<PhaseTimes>,
+    format: FpsFormat,
+}
+
+/// Time spent in each phase of a frame.
+#[derive(Clone, Copy, Default)]
+pub struct PhaseTimes {
+    pub update: Duration,
+    pub render: Duration,
+    pub diff: Duration,
+}
+
+impl FpsTracker {
+    pub fn new(target_fps: u32, profile: bool, format: FpsFormat) -> Self {
+        let fps_file_path = exe_relative("fps.txt");
+        let history_file_path = exe_relative("fps_history.txt");
+
+        Self {
+            window_frames: 0,
+            total_frames: 0,
+            window_start: Instant::now(),
+            fps_file_path,
+            history_file_path,
+            second_frames: 0,
+            second_start: Instant::now(),
+            last_second_fps: 0.0,
+            target_fps,
+            phases: profile.then(PhaseTimes::default),
+            format,
+        }
+    }
+
+    pub fn tick(&mut self, phases: PhaseTimes) {
+        if let Some(total) = &mut self.phases {
+            total.update += phases.update;
+            total.render += phases.render;
+            total.diff += phases.diff;
+        }
+        self.window_frames += 1;
+        self.total_frames += 1;
+
+        self.second_frames += 1;
+        let second = self.second_start.elapsed();
+        if second >= Duration::from_secs(1) {
+            self.last_second_fps = self.second_frames as f64 / second.as_secs_f64();
+            self.second_frames = 0;
+            self.second_start = Instant::now();
+        }
+
+        let elapsed = self.window_start.elapsed();
+        if elapsed >= Duration::from_secs(10) {
+            let fps = self.window_frames as f64 / elapsed.as_secs_f64();
+            self.log_fps(fps);
+            self.window_frames = 0;
+            self.window_start = Instant::now();
+            if let Some(total) = &mut self.phases {
+                *total = PhaseTimes::default();
+            }
+        }
+    }
+
+    /// Frame rate over the last completed second, for the on-screen readout.
+    pub fn current_fps(&self) -> f64 {
+        self.last_second_fps
+    }
+
+    fn log_fps(&self, fps: f64) {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let frames = self.window_frames.max(1) as u128;
+        let phase_us = self.phases.map(|total| {
+            [
+                total.update.as_micros() / frames,
+                total.render.as_micros() / frames,
+                total.diff.as_micros() / frames,
+            ]
+        });
+        let mut line = match self.format {
+            FpsFormat::Text => format!(
+                "timestamp={} fps={:.1} target_fps={} total_frames={}",
+                timestamp, fps, self.target_fps, self.total_frames
+            ),
+            FpsFormat::Json => format!(
+                "{{\"ts\":{},\"fps\":{:.1},\"target\":{},\"total\":{}",
+                timestamp, fps, self.target_fps, self.total_frames
+            ),
+        };
+        if let Some([update, render, diff]) = phase_us {
+            line.push_str(&match self.format {
+                FpsFormat::Text => {
+                    format!(" update_us={} render_us={} diff_us={}", update, render, diff)
+                }
+                FpsFormat::Json => format!(
+                    ",\"update_us\":{},\"render_us\":{},\"diff_us\":{}",
+                    update, render, diff
+                ),
+            });
+        }
+        if self.format == FpsFormat::Json {
+            line.push('}');
+        }
+        line.push('\n');
+        let appended = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.history_file_path)
+            .and_then(|mut f| f.write_all(line.as_bytes()));
+        if let Err(e) = appended {
+            log_failure(&format!("write {}", self.history_file_path.display()), &e);
+        }
+
+        // Write-then-rename: readers see either the old or the new record
+        let tmp_path = self.fps_file_path.with_extension("txt.tmp");
+        let replaced = std::fs::write(&tmp_path, line.as_bytes())
+            .and_then(|()| std::fs::rename(&tmp_path, &self.fps_file_path));
+        if let Err(e) = replaced {
+            log_failure(&format!("write {}", self.fps_file_path.display()), &e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_menu_rect_is_not_written_by_drops() {
+        let (cols, rows) = (100usize, 40usize);
+        let mut app = App::new(cols as u16, rows as u16, Config::default(), GlyphSet::builtin());
+        for _ in 0..200 {
+            app.update();
+        }
+        app.menu_open = true;
+
+        let mut buf = vec![Cell::BLANK; cols * rows];
+        render_drops(&mut buf, &mut DirtyCells::new(false), &app);
+
+        let rect = MenuRect::for_screen(cols, rows);
+        for r in 0..rows {
+            for c in 0..cols {
+                if rect.contains(r, c) {
+                    assert!(buf[r * cols + c] == Cell::BLANK, "drop drawn at {},{}", r, c);
+                }
+            }
+        }
+        assert!(buf.iter().any(|&cell| cell != Cell::BLANK));
+    }
+
+    fn app_with_categories(categories: &[(&str, &[&str])]) -> App {
+        let mut app = App::new(80, 30, Config::default(), GlyphSet::builtin());
+        app.menu.categories = categories
+            .iter()
+            .map(|&(name, entries)| PayloadCategory {
+                name: name.to_string(),
+                label: name.to_string(),
+                entries: entries
+                    .iter()
+                    .map(|&e| PayloadEntry {
+                        name: e.to_string(),
+                        path: PathBuf::from(e),
+                        kind: ScriptKind::PowerShell,
+                        args: Vec::new(),
+                    })
+                    .collect(),
+                expanded: true,
+            })
+            .collect();
+        app.menu.cursor = MenuIndex::Category(0);
+        app.menu.rebuild_items();
+        app
+    }
+
+    #[test]
+    fn empty_expanded_category_never_yields_an_entry_cursor() {
+        let mut app = app_with_categories(&[("empty", &[]), ("tools", &["a.ps1"])]);
+
+        app.menu.move_down();
+        assert!(matches!(app.menu.cursor, MenuIndex::Category(1)));
+        app.menu.move_down();
+        assert!(matches!(app.menu.cursor, MenuIndex::Entry(1, 0)));
+        app.menu.move_home();
+        assert!(matches!(app.menu.cursor, MenuIndex::Category(0)));
+
+        // Enter on the empty category folds it rather than queueing a launch
+        app.activate_selection();
+        assert!(app.pending_launch.is_none());
+        assert!(!app.menu.categories[0].expanded);
+        app.menu.expand_selected();
+        app.menu.collapse_selected();
+        app.menu.move_end();
+        assert!(matches!(app.menu.cursor, MenuIndex::Entry(1, 0)));
+    }
+
+    #[test]
+    fn click_during_launch_prompt_declines_it() {
+        let mut app = app_with_categories(&[("tools", &["a.ps1", "b.ps1"])]);
+        app.menu_open = true;
+        app.menu.move_down();
+        app.activate_selection();
+        assert_eq!(app.pending_launch, Some((0, 0)));
+
+        // Outside the menu: the prompt goes, the menu stays
+        app.click(0, 0);
+        assert!(app.pending_launch.is_none());
+        assert!(app.menu_open);
+        assert!(app.running.is_empty());
+
+        // On another row: declined, not retargeted
+        app.activate_selection();
+        let rect = MenuRect::for_screen(app.cols as usize, app.rows as usize);
+        let row = (rect.y..rect.y + rect.h)
+            .find(|&r| app.menu.item_at(rect, r, rect.x + 6) == Some((false, 0, 1)))
+            .expect("row of b.ps1");
+        app.click((rect.x + 6) as u16, row as u16);
+        assert!(app.pending_launch.is_none());
+        assert!(matches!(app.menu.cursor, MenuIndex::Entry(0, 0)));
+    }
+
+    #[test]
+    fn menu_draws_non_ascii_names_cell_by_cell() {
+        let long = format!("r\u{e9}con {}.ps1", "\u{5c71}".repeat(60));
+        let mut app = app_with_categories(&[("r\u{e9}con", &["\u{e9}t\u{e9}.ps1", &long])]);
+        app.config.rain_enabled = false;
+        app.menu_open = true;
+        app.menu.move_down();
+        app.activate_selection();
+        let text = render_text(&app);
+
+        assert!(text.contains(" v r\u{e9}con"));
+        assert!(text.contains("     \u{e9}t\u{e9}.ps1 "));
+        assert!(text.contains("Run \u{e9}t\u{e9}.ps1? [Y/N]"));
+        // Wide glyphs count two cells each, so the ellipsis still fits
+        let row = text.lines().find(|l| l.contains("r\u{e9}con \u{5c71}")).expect("long row");
+        assert!(row.contains("\u{5c71}...ps1 "));
+    }
+
+    #[test]
+    fn key_codes_need_a_prefix_below_ten() {
+        assert!(Key::parse("9") == Some(Key::Char(b'9' as u16)));
+        assert!(Key::parse("vk:9") == Some(Key::Vk(VK_TAB)));
+        assert!(Key::parse("0x09") == Some(Key::Vk(VK_TAB)));
+        assert!(Key::parse("vk:0x70") == Some(Key::Vk(0x70)));
+        assert!(Key::parse("112") == Some(Key::Vk(0x70)));
+        assert!(Key::parse("vk:").is_none());
+
+        let x = Key::parse("x").unwrap();
+        assert!(x.matches(0, b'x' as u16) && x.matches(0, b'X' as u16));
+        assert!(!Key::parse("1").unwrap().matches(0, b'!' as u16));
+    }
+
+    #[test]
+    fn crossed_bounds_from_the_file_name_the_file() {
+        let path = std::env::temp_dir().join(format!("badderblood-{}.toml", std::process::id()));
+        fs::write(&path, "trail_min = 50\ntrail-max = 10\n").unwrap();
+        let mut config = Config::default();
+        let applied = config.apply_file(&path);
+        fs::remove_file(&path).unwrap();
+        applied.unwrap();
+
+        let err = config.check_ranges().unwrap_err();
+        let at = path.display();
+        assert_eq!(
+            err,
+            format!("trail_min = 50 ({}:1) is greater than trail-max = 10 ({}:2)", at, at)
+        );
+
+        // A flag given afterwards is reported as the flag
+        let mut args = ["20".to_string()].into_iter();
+        config.set_from("trail-max", &mut OptionSource::Args(&mut args)).unwrap();
+        let err = config.check_ranges().unwrap_err();
+        assert_eq!(err, format!("trail_min = 50 ({}:1) is greater than --trail-max 20", at));
+    }
+
+    #[test]
+    fn wind_moves_slow_and_fast_drops_alike() {
+        let config = Config::default();
+        let glyphs = GlyphSet::builtin();
+        let mut rng = Rng::from_seed(1);
+        let cols = |speed: u8, rng: &mut Rng| {
+            let mut drop = Drop::new(10, 30, &config, rng);
+            drop.speed = speed;
+            drop.streak = false;
+            for _ in 0..8 {
+                drop.update((80, 30), 1, &config, &glyphs, (0, 1), rng);
+            }
+            drop.col
+        };
+        assert_eq!(cols(1, &mut rng), 18);
+        assert_eq!(cols(6, &mut rng), 18);
+    }
+
+    fn render_text(app: &App) -> String {
+        let (cols, rows) = (app.cols as usize, app.rows as usize);
+        let mut buf = vec![Cell::BLANK; cols * rows];
+        render_to_buffer(&mut buf, app, &mut DirtyCells::new(false));
+        cells_to_string(&buf, cols, rows)
+    }
+
+    #[test]
+    fn status_bar_sits_bottom_right() {
+        let config = Config { rain_enabled: false, ..Config::default() };
+        let app = App::new(80, 10, config, GlyphSet::builtin());
+        let text = render_text(&app);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 10);
+        assert!(lines[..9].iter().all(|l| l.trim().is_empty()));
+        let status = " BADDERBLOOD // frame 0 // Tab for menu // q to quit ";
+        assert_eq!(lines[9], format!("{:>79} ", status));
+    }
+
+    #[test]
+    fn status_bar_measures_non_ascii_keys_in_cells() {
+        let mut config = Config { rain_enabled: false, ..Config::default() };
+        config.keys.menu = vec![Key::Char(0x5C71)];
+        config.keys.quit = vec![Key::Char(0xE9)];
+        let app = App::new(80, 10, config, GlyphSet::builtin());
+        let text = render_text(&app);
+        let last = text.lines().last().expect("status row").to_string();
+        let status = " BADDERBLOOD // frame 0 // \u{5c71} for menu // \u{e9} to quit ";
+        // The wide glyph takes two cells, so one less space of padding
+        let pad = 80 - 1 - text_width(status);
+        assert_eq!(last, format!("{}{} ", " ".repeat(pad), status));
+    }
+
+    #[test]
+    fn menu_lists_categories_and_entries() {
+        let mut app = app_with_categories(&[("tools", &["a.ps1", "b.cmd"]), ("empty", &[])]);
+        app.config.rain_enabled = false;
+        app.menu_open = true;
+        let text = render_text(&app);
+        let lines: Vec<&str> = text.lines().collect();
+
+        let title = lines
+            .iter()
+            .position(|l| l.contains(" BadderBlood // Payload Launcher "))
+            .expect("menu title");
+        let tools = lines.iter().position(|l| l.contains(" v tools")).expect("category row");
+        assert!(tools > title);
+        assert!(lines[tools + 1].contains("     a.ps1 "));
+        assert!(lines[tools + 2].contains("     b.cmd "));
+        assert!(lines[tools + 3].contains(" v empty"));
+        assert!(lines[lines.len() - 1].contains("BADDERBLOOD // frame 0"));
+    }
+
+    #[test]
+    fn seeded_rain_renders_the_same_frames() {
+        let frame = || {
+            let config = Config { seed: Some(7), ..Config::default() };
+            let mut app = App::new(60, 20, config, GlyphSet::builtin());
+            for _ in 0..40 {
+                app.update();
+            }
+            render_text(&app)
+        };
+        let first = frame();
+        assert_eq!(first, frame());
+        assert!(first.lines().take(19).any(|l| !l.trim().is_empty()));
+    }
+
+    #[test]
+    fn menu_keys_do_nothing_without_categories() {
+        let mut app = app_with_categories(&[]);
+        app.menu.move_down();
+        app.menu.page_down(10);
+        app.menu.expand_selected();
+        app.menu.collapse_selected();
+        app.activate_selection();
+        assert!(app.pending_launch.is_none());
+
+        // A filter that hides every row leaves nothing selectable either
+        let mut app = app_with_categories(&[("tools", &["a.ps1"])]);
+        app.menu.move_down();
+        app.menu.push_filter('z');
+        app.activate_selection();
+        assert!(app.pending_launch.is_none());
+    }
+
+    fn payload(path: &str, kind: ScriptKind, args: &[&str]) -> PayloadEntry {
+        PayloadEntry {
+            name: path.to_string(),
+            path: PathBuf::from(path),
+            kind,
+            args: args.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+
+    fn command_args(command: &Command) -> Vec<String> {
+        command.get_args().map(|a| a.to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn powershell_payload_path_stays_one_absolute_argument() {
+        let name = "payload/Red Team/r\u{e9}con \u{5c71}.ps1";
+        let entry = payload(name, ScriptKind::PowerShell, &["-Target", "two words"]);
+        let command = payload_command(&entry, ExecPolicy::Bypass);
+        assert_eq!(command.get_program(), "powershell.exe");
+
+        let args = command_args(&command);
+        assert_eq!(args.len(), 6);
+        assert_eq!(args[..3], ["-ExecutionPolicy", "Bypass", "-File"]);
+        let script = std::path::Path::new(&args[3]);
+        assert!(script.is_absolute());
+        assert!(script.ends_with("payload/Red Team/r\u{e9}con \u{5c71}.ps1"));
+        assert_eq!(args[4..], ["-Target", "two words"]);
+    }
+
+    #[test]
+    fn missing_batch_script_is_reported_by_path() {
+        let missing = || Err(io::Error::from(io::ErrorKind::NotFound));
+        let batch = payload("payload/tools/gone.cmd", ScriptKind::Batch, &[]);
+        let msg = launch_outcome(&batch, &missing());
+        assert_eq!(msg.text, "Failed: payload/tools/gone.cmd not found");
+        let ps = payload("payload/tools/gone.ps1", ScriptKind::PowerShell, &[]);
+        assert_eq!(launch_outcome(&ps, &missing()).text, "Failed: powershell not found");
+    }
+
+    #[test]
+    fn batch_payload_is_spawned_directly() {
+        let entry = payload("payload/Blue Team/sw\u{e9}ep.cmd", ScriptKind::Batch, &["a b"]);
+        let command = payload_command(&entry, ExecPolicy::Bypass);
+        let program = std::path::Path::new(command.get_program());
+        assert!(program.is_absolute());
+        assert!(program.ends_with("payload/Blue Team/sw\u{e9}ep.cmd"));
+        assert_eq!(command_args(&command), ["a b"]);
+    }
+
+    /// A `cols` x `rows` grid of blanks with `cell` written at (`r`, `c`).
+    fn grid_with(cols: usize, rows: usize, r: usize, c: usize, cell: Cell) -> Vec<Cell> {
+        let mut grid = vec![Cell::BLANK; cols * rows];
+        grid[r * cols + c] = cell;
+        grid
+    }
+
+    #[test]
+    fn unchanged_frame_emits_nothing() {
+        let cur = grid_with(8, 4, 1, 2, Cell { ch: b'x' as u16, attr: 0x0A });
+        let mut prev = cur.clone();
+        let mut vt = VtRenderer::new(64, false);
+        assert!(vt.render_diff(&cur, &mut prev, 8, 4, None).is_empty());
+        assert!(vt.render_diff(&cur, &mut prev, 8, 4, Some(&[0, 10, 31])).is_empty());
+    }
+
+    #[test]
+    fn one_changed_cell_is_one_move_colour_glyph_and_reset() {
+        let (cols, rows) = (8, 4);
+        let cur = grid_with(cols, rows, 1, 2, Cell { ch: b'x' as u16, attr: 0x0A });
+        let mut prev = vec![Cell::BLANK; cols * rows];
+        let mut vt = VtRenderer::new(64, false);
+
+        let expected: &[u8] = b"\x1b[2;3H\x1b[92;40mx\x1b[0m";
+        assert_eq!(vt.render_diff(&cur, &mut prev, cols, rows, None), expected);
+        assert!(prev == cur);
+
+        // The candidate list walks the same cells to the same bytes
+        let mut prev = vec![Cell::BLANK; cols * rows];
+        let candidates = [0, (cols + 2) as u32, 20];
+        assert_eq!(vt.render_diff(&cur, &mut prev, cols, rows, Some(&candidates)), expected);
+
+        // And the next identical frame has nothing left to say
+        assert!(vt.render_diff(&cur, &mut prev, cols, rows, None).is_empty());
+    }
+
+    #[test]
+    fn transparent_rain_keeps_the_default_background() {
+        let (cols, rows) = (8, 4);
+        let indexed = Cell { ch: b'x' as u16, attr: ATTR_INDEXED | 46 };
+        let cur = grid_with(cols, rows, 0, 0, indexed);
+        let mut prev = vec![Cell::BLANK; cols * rows];
+        let mut vt = VtRenderer::new(64, true);
+        let expected: &[u8] = b"\x1b[1;1H\x1b[38;5;46;49mx\x1b[0m";
+        assert_eq!(vt.render_diff(&cur, &mut prev, cols, rows, None), expected);
+    }
+}
@@ -1,19 +1,29 @@
 #![cfg(windows)]
 
+use badderblood::{
+    log_failure, render_to_buffer, title_sequence, App, Cell, Config, DirtyCells, FpsTracker,
+    GlyphSet, InputAction, KeyMap, Menu, PhaseTimes, VtRenderer, VERBOSE,
+};
 use std::{
-    fs,
+    fs::File,
     io::{self, Write},
-    path::PathBuf,
-    process::Command,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
     time::{Duration, Instant},
 };
+use windows_sys::core::BOOL;
 use windows_sys::Win32::{
-    Foundation::HANDLE,
+    Foundation::{HANDLE, SYSTEMTIME},
     System::Console::{
-        GetConsoleMode, GetConsoleScreenBufferInfo, GetNumberOfConsoleInputEvents, GetStdHandle,
-        ReadConsoleInputW, SetConsoleMode, CONSOLE_SCREEN_BUFFER_INFO,
-        ENABLE_EXTENDED_FLAGS, ENABLE_VIRTUAL_TERMINAL_PROCESSING, ENABLE_WINDOW_INPUT,
-        INPUT_RECORD, KEY_EVENT, STD_INPUT_HANDLE, STD_OUTPUT_HANDLE, WINDOW_BUFFER_SIZE_EVENT,
+        GetConsoleMode, GetConsoleOutputCP, GetConsoleScreenBufferInfo, GetConsoleTitleW,
+        GetNumberOfConsoleInputEvents, GetStdHandle, ReadConsoleInputW, SetConsoleMode,
+        SetConsoleCtrlHandler, SetConsoleOutputCP, SetConsoleTitleW, CONSOLE_SCREEN_BUFFER_INFO,
+        CTRL_BREAK_EVENT, CTRL_CLOSE_EVENT, CTRL_C_EVENT,
+        ENABLE_EXTENDED_FLAGS, ENABLE_MOUSE_INPUT, ENABLE_VIRTUAL_TERMINAL_PROCESSING,
+        ENABLE_WINDOW_INPUT, FROM_LEFT_1ST_BUTTON_PRESSED, INPUT_RECORD, KEY_EVENT, MOUSE_EVENT,
+        STD_INPUT_HANDLE, STD_OUTPUT_HANDLE, WINDOW_BUFFER_SIZE_EVENT,
     },
 };
 
@@ -23,6 +33,8 @@ extern "system" {
     fn timeEndPeriod(uPeriod: u32) -> u32;
 }
 
+const CP_UTF8: u32 = 65001;
+
 extern "system" {
     fn WriteFile(
         hFile: HANDLE,
@@ -31,435 +43,168 @@ extern "system" {
         lpNumberOfBytesWritten: *mut u32,
         lpOverlapped: *mut std::ffi::c_void,
     ) -> i32;
+    fn GetLocalTime(lpSystemTime: *mut SYSTEMTIME);
 }
 
 // ---------------------------------------------------------------------------
-// Fast xoshiro256++ PRNG – vastly faster than rand::thread_rng()
-// ---------------------------------------------------------------------------
-
-struct Rng {
-    s: [u64; 4],
-}
-
-impl Rng {
-    fn new() -> Self {
-        let t = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_nanos() as u64;
-        let mut s = [
-            t ^ 0x9E3779B97F4A7C15,
-            t.wrapping_mul(0x6C62272E07BB0142) ^ 0xBF58476D1CE4E5B9,
-            t.wrapping_mul(0x94D049BB133111EB) ^ 0x853C49E6748FEA9B,
-            t.rotate_left(17) ^ 0x2545F4914F6CDD1D,
-        ];
-        for _ in 0..8 {
-            let t = s[1] << 17;
-            s[2] ^= s[0];
-            s[3] ^= s[1];
-            s[1] ^= s[2];
-            s[0] ^= s[3];
-            s[2] ^= t;
-            s[3] = s[3].rotate_left(45);
-        }
-        Self { s }
-    }
-
-    #[inline(always)]
-    fn next_u64(&mut self) -> u64 {
-        let result = (self.s[0].wrapping_add(self.s[3]))
-            .rotate_left(23)
-            .wrapping_add(self.s[0]);
-        let t = self.s[1] << 17;
-        self.s[2] ^= self.s[0];
-        self.s[3] ^= self.s[1];
-        self.s[1] ^= self.s[2];
-        self.s[0] ^= self.s[3];
-        self.s[2] ^= t;
-        self.s[3] = self.s[3].rotate_left(45);
-        result
-    }
-
-    #[inline(always)]
-    fn gen_u32(&mut self, n: u32) -> u32 {
-        let r = self.next_u64() as u32;
-        ((r as u64).wrapping_mul(n as u64) >> 32) as u32
-    }
-
-    #[inline(always)]
-    fn gen_range(&mut self, lo: i32, hi_exclusive: i32) -> i32 {
-        let span = (hi_exclusive - lo) as u32;
-        lo + self.gen_u32(span) as i32
-    }
-
-    #[inline(always)]
-    fn gen_range_u16(&mut self, lo: u16, hi_inclusive: u16) -> u16 {
-        let span = (hi_inclusive - lo + 1) as u32;
-        lo + self.gen_u32(span) as u16
-    }
-
-    #[inline(always)]
-    fn gen_bool(&mut self, p_numer: u32, p_denom: u32) -> bool {
-        self.gen_u32(p_denom) < p_numer
-    }
-}
-
-// ---------------------------------------------------------------------------
-// Character set – ASCII chars that evoke the Matrix aesthetic
-// ---------------------------------------------------------------------------
-
-const MATRIX_CHARS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz+-*=<>:;|~!@#$%^&(){}[]/?\\";
-
-#[inline(always)]
-fn random_char_u16(rng: &mut Rng) -> u16 {
-    MATRIX_CHARS[rng.gen_u32(MATRIX_CHARS.len() as u32) as usize] as u16
-}
-
-// ---------------------------------------------------------------------------
-// Console colour attributes (Win32 4-bit) -> ANSI SGR mapping
-// ---------------------------------------------------------------------------
-
-const ATTR_BLACK: u16 = 0x0000;
-const ATTR_HEAD: u16 = 0x0F;    // bright white
-const ATTR_NEAR1: u16 = 0x0A;   // bright green
-const ATTR_NEAR2: u16 = 0x0A;
-const ATTR_TRAIL_BRIGHT: u16 = 0x0A;
-const ATTR_TRAIL_DIM: u16 = 0x02;  // dark green
-const ATTR_STATUS: u16 = 0x02;
-const ATTR_MSG: u16 = 0x0A;
-
-const TRAIL_PALETTE_SIZE: usize = 16;
-
-struct AttrPalette {
-    head: u16,
-    near_head: [u16; 2],
-    trail: [u16; TRAIL_PALETTE_SIZE],
-}
-
-fn build_attr_palette() -> AttrPalette {
-    let mut trail = [0u16; TRAIL_PALETTE_SIZE];
-    let bright_end = TRAIL_PALETTE_SIZE * 6 / 10;
-    for i in 0..TRAIL_PALETTE_SIZE {
-        trail[i] = if i < bright_end {
-            ATTR_TRAIL_BRIGHT
-        } else {
-            ATTR_TRAIL_DIM
-        };
-    }
-    AttrPalette {
-        head: ATTR_HEAD,
-        near_head: [ATTR_NEAR1, ATTR_NEAR2],
-        trail,
-    }
-}
-
-/// Map a Win32 4-bit console attribute to an ANSI SGR byte sequence.
-fn attr_to_sgr(attr: u16) -> &'static [u8] {
-    match attr {
-        0x0F => b"\x1b[97;40m",      // bright white on black
-        0x0A => b"\x1b[92;40m",      // bright green on black
-        0x02 => b"\x1b[32;40m",      // dark green on black
-        0x20 => b"\x1b[30;42m",      // black on green (menu selection)
-        0x04 => b"\x1b[31;40m",      // red on black (error text)
-        _ => b"\x1b[0m",             // reset (black/default)
-    }
-}
-
-// ---------------------------------------------------------------------------
-// Cell type for our logical framebuffer
-// ---------------------------------------------------------------------------
-
-#[derive(Clone, Copy, PartialEq, Eq)]
-struct Cell {
-    ch: u16,    // UTF-16 code unit
-    attr: u16,  // Win32 attribute value
-}
-
-impl Cell {
-    const BLANK: Cell = Cell { ch: b' ' as u16, attr: ATTR_BLACK };
-}
-
-// ---------------------------------------------------------------------------
-// A single falling "stream" – compact ring buffer
+// Win32 console helpers
 // ---------------------------------------------------------------------------
 
-const MAX_TRAIL: usize = 128;
-
-struct Drop {
-    col: u16,
-    head: i32,
-    chars: [u16; MAX_TRAIL],
-    len: u16,
-    write_pos: u16,
-    max_len: u16,
-    speed: u8,
-    tick: u8,
-    glitch: bool,
-}
-
-impl Drop {
-    fn new(col: u16, rows: u16, rng: &mut Rng) -> Self {
-        let max_len = rng.gen_range_u16(8, rows.min(MAX_TRAIL as u16));
-        let speed = rng.gen_range(1, 5) as u8;
-        let head = -(rng.gen_range(0, rows as i32 + 10));
-        Self {
-            col,
-            head,
-            chars: [b' ' as u16; MAX_TRAIL],
-            len: 0,
-            write_pos: 0,
-            max_len,
-            speed,
-            tick: 0,
-            glitch: rng.gen_bool(35, 100),
-        }
-    }
-
-    fn reset(&mut self, rows: u16, rng: &mut Rng) {
-        self.head = -(rng.gen_range(0, rows as i32 / 2 + 5));
-        self.max_len = rng.gen_range_u16(8, rows.min(MAX_TRAIL as u16));
-        self.speed = rng.gen_range(1, 5) as u8;
-        self.len = 0;
-        self.write_pos = 0;
-        self.glitch = rng.gen_bool(35, 100);
-    }
-
-    #[inline]
-    fn update(&mut self, rows: u16, rng: &mut Rng) {
-        self.tick += 1;
-        if self.tick < self.speed {
-            return;
-        }
-        self.tick = 0;
-        self.head += 1;
-
-        let ml = self.max_len;
-        self.chars[self.write_pos as usize] = random_char_u16(rng);
-        self.write_pos = (self.write_pos + 1) % ml;
-        if self.len < ml {
-            self.len += 1;
-        }
-
-        if self.glitch && self.len > 2 && rng.gen_bool(3, 10) {
-            let idx = rng.gen_u32(self.len as u32 - 1) + 1;
-            let ring_idx = (self.write_pos + ml - 1 - idx as u16) % ml;
-            self.chars[ring_idx as usize] = random_char_u16(rng);
-        }
-
-        let tail_row = self.head - self.len as i32;
-        if tail_row > rows as i32 {
-            self.reset(rows, rng);
-        }
+/// Log `GetLastError` if a BOOL-returning Win32 call failed.
+fn check_win32(what: &str, ok: i32) {
+    if ok == 0 {
+        log_failure(what, &io::Error::last_os_error());
     }
-
-    #[inline(always)]
-    fn trail_char_u16(&self, i: u16) -> u16 {
-        let ring_idx = (self.write_pos + self.max_len - 1 - i) % self.max_len;
-        self.chars[ring_idx as usize]
-    }
-}
-
-// ---------------------------------------------------------------------------
-// Payload menu
-// ---------------------------------------------------------------------------
-
-struct PayloadEntry {
-    name: String,
-    path: PathBuf,
-}
-
-struct PayloadCategory {
-    name: String,
-    entries: Vec<PayloadEntry>,
-    expanded: bool,
-}
-
-#[derive(Clone)]
-enum MenuIndex {
-    Category(usize),
-    Entry(usize, usize),
 }
 
-struct Menu {
-    categories: Vec<PayloadCategory>,
-    cursor: MenuIndex,
-    scroll_offset: usize,
+/// Where console output goes: every write funnels through `write`, which
+/// also tees the bytes into the `--record` file when there is one.
+struct ConsoleOut {
+    handle: HANDLE,
+    chunk: usize,
+    record: Option<File>,
 }
 
-impl Menu {
-    fn load() -> Self {
-        let payload_dir = std::env::current_exe()
-            .ok()
-            .and_then(|p| p.parent().map(|d| d.join("payload")))
-            .unwrap_or_else(|| PathBuf::from("payload"));
-
-        let mut categories = Vec::new();
-
-        if let Ok(entries) = fs::read_dir(&payload_dir) {
-            let mut dirs: Vec<PathBuf> = entries
-                .filter_map(|e| e.ok())
-                .map(|e| e.path())
-                .filter(|p| p.is_dir())
-                .collect();
-            dirs.sort();
-
-            for dir in dirs {
-                let dir_name = dir
-                    .file_name()
-                    .unwrap_or_default()
-                    .to_string_lossy()
-                    .to_string();
-
-                let mut ps1_entries = Vec::new();
-                if let Ok(files) = fs::read_dir(&dir) {
-                    let mut file_paths: Vec<PathBuf> = files
-                        .filter_map(|e| e.ok())
-                        .map(|e| e.path())
-                        .filter(|p| {
-                            p.is_file()
-                                && p.extension()
-                                    .map(|ext| ext.eq_ignore_ascii_case("ps1"))
-                                    .unwrap_or(false)
-                        })
-                        .collect();
-                    file_paths.sort();
-
-                    for fp in file_paths {
-                        let name = fp
-                            .file_name()
-                            .unwrap_or_default()
-                            .to_string_lossy()
-                            .to_string();
-                        ps1_entries.push(PayloadEntry {
-                            name,
-                            path: fp,
-                        });
-                    }
-                }
-
-                categories.push(PayloadCategory {
-                    name: dir_name,
-                    entries: ps1_entries,
-                    expanded: true,
-                });
-            }
-        }
-
-        Menu {
-            categories,
-            cursor: MenuIndex::Category(0),
-            scroll_offset: 0,
-        }
-    }
-
-    fn visible_items(&self) -> Vec<(bool, usize, usize)> {
-        let mut items = Vec::new();
-        for (ci, cat) in self.categories.iter().enumerate() {
-            items.push((true, ci, 0));
-            if cat.expanded {
-                for ei in 0..cat.entries.len() {
-                    items.push((false, ci, ei));
-                }
-            }
+impl ConsoleOut {
+    fn write(&mut self, bytes: &[u8]) {
+        if let Err(e) = write_console(self.handle, bytes, self.chunk) {
+            log_failure("console write", &e);
         }
-        items
-    }
-
-    fn cursor_flat_index(&self) -> usize {
-        let items = self.visible_items();
-        for (i, &(is_cat, ci, ei)) in items.iter().enumerate() {
-            match &self.cursor {
-                MenuIndex::Category(c) if is_cat && *c == ci => return i,
-                MenuIndex::Entry(c, e) if !is_cat && *c == ci && *e == ei => return i,
-                _ => {}
+        if let Some(file) = &mut self.record {
+            if let Err(e) = file.write_all(bytes) {
+                // Stop recording rather than log the same failure every frame
+                log_failure("record write", &e);
+                self.record = None;
             }
         }
-        0
     }
+}
 
-    fn move_up(&mut self) {
-        let items = self.visible_items();
-        if items.is_empty() {
-            return;
-        }
-        let idx = self.cursor_flat_index();
-        if idx > 0 {
-            let (is_cat, ci, ei) = items[idx - 1];
-            self.cursor = if is_cat {
-                MenuIndex::Category(ci)
-            } else {
-                MenuIndex::Entry(ci, ei)
-            };
+/// Write all of `bytes` to the console in `WriteFile` calls of at most
+/// `chunk` bytes, continuing after partial writes. Big repaints are split so
+/// console hosts that stall on huge writes get a chance to catch up.
+fn write_console(handle: HANDLE, bytes: &[u8], chunk: usize) -> io::Result<()> {
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let n = rest.len().min(chunk).min(u32::MAX as usize);
+        let mut written: u32 = 0;
+        let ok = unsafe {
+            WriteFile(handle, rest.as_ptr(), n as u32, &mut written, std::ptr::null_mut())
+        };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
         }
-    }
-
-    fn move_down(&mut self) {
-        let items = self.visible_items();
-        if items.is_empty() {
-            return;
+        if written == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
         }
-        let idx = self.cursor_flat_index();
-        if idx + 1 < items.len() {
-            let (is_cat, ci, ei) = items[idx + 1];
-            self.cursor = if is_cat {
-                MenuIndex::Category(ci)
-            } else {
-                MenuIndex::Entry(ci, ei)
-            };
+        rest = &rest[written as usize..];
+        if !rest.is_empty() {
+            std::thread::yield_now();
         }
     }
+    Ok(())
 }
 
-fn launch_ps1(path: &PathBuf) {
-    let _ = Command::new("powershell.exe")
-        .args(["-ExecutionPolicy", "Bypass", "-File"])
-        .arg(path)
-        .spawn();
+/// Local time of day as (hours, minutes, seconds).
+fn local_clock() -> (u8, u8, u8) {
+    let mut now: SYSTEMTIME = unsafe { std::mem::zeroed() };
+    unsafe { GetLocalTime(&mut now) };
+    (now.wHour as u8, now.wMinute as u8, now.wSecond as u8)
 }
 
-// ---------------------------------------------------------------------------
-// Win32 console helpers
-// ---------------------------------------------------------------------------
+/// The console window title, to put back on exit.
+fn get_console_title() -> Vec<u16> {
+    let mut title = vec![0u16; 1024];
+    let len = unsafe { GetConsoleTitleW(title.as_mut_ptr(), title.len() as u32) };
+    title.truncate(len as usize);
+    title
+}
 
-fn get_console_size(handle: HANDLE) -> (u16, u16) {
+/// Buffer coordinates of the window's top-left cell; mouse events report
+/// buffer positions, which differ from window ones once there is scrollback.
+fn get_window_origin(handle: HANDLE) -> (i16, i16) {
     unsafe {
         let mut info: CONSOLE_SCREEN_BUFFER_INFO = std::mem::zeroed();
-        GetConsoleScreenBufferInfo(handle, &mut info);
-        let w = (info.srWindow.Right - info.srWindow.Left + 1) as u16;
-        let h = (info.srWindow.Bottom - info.srWindow.Top + 1) as u16;
-        (w, h)
+        check_win32(
+            "GetConsoleScreenBufferInfo",
+            GetConsoleScreenBufferInfo(handle, &mut info),
+        );
+        (info.srWindow.Left, info.srWindow.Top)
+    }
+}
+
+/// Visible window size in cells, or `None` if the console won't say or
+/// reports an empty window.
+fn get_console_size(handle: HANDLE) -> Option<(u16, u16)> {
+    let mut info: CONSOLE_SCREEN_BUFFER_INFO = unsafe { std::mem::zeroed() };
+    if unsafe { GetConsoleScreenBufferInfo(handle, &mut info) } == 0 {
+        log_failure("GetConsoleScreenBufferInfo", &io::Error::last_os_error());
+        return None;
     }
+    let w = info.srWindow.Right as i32 - info.srWindow.Left as i32 + 1;
+    let h = info.srWindow.Bottom as i32 - info.srWindow.Top as i32 + 1;
+    (w > 0 && h > 0).then_some((w as u16, h as u16))
 }
 
+/// Size to start at when the console doesn't report one.
+const FALLBACK_SIZE: (u16, u16) = (80, 25);
+
 // ---------------------------------------------------------------------------
 // Win32 keyboard input
 // ---------------------------------------------------------------------------
 
+const VK_BACK: u16 = 0x08;
+const VK_PRIOR: u16 = 0x21;
+const VK_NEXT: u16 = 0x22;
+const VK_END: u16 = 0x23;
+const VK_HOME: u16 = 0x24;
 const VK_RETURN: u16 = 0x0D;
 const VK_ESCAPE: u16 = 0x1B;
-const VK_TAB: u16 = 0x09;
 const VK_LEFT: u16 = 0x25;
 const VK_UP: u16 = 0x26;
 const VK_RIGHT: u16 = 0x27;
 const VK_DOWN: u16 = 0x28;
 
-enum InputAction {
-    None,
-    Quit,
-    Tab,
-    Enter,
-    Escape,
-    Up,
-    Down,
-    Left,
-    Right,
-    Resize(u16, u16),
-}
-
-fn poll_input(stdin_handle: HANDLE, stdout_handle: HANDLE) -> InputAction {
-    let mut action = InputAction::None;
-
-    loop {
+/// What the UI is doing, which changes how keys are interpreted.
+#[derive(Clone, Copy)]
+struct InputMode {
+    /// Printable keys arrive as `Char` (and Backspace as `Backspace`)
+    /// instead of their command meaning.
+    text_entry: bool,
+    /// Any key press or mouse event is a `Quit`.
+    screensaver: bool,
+    /// Vim keys navigate: `j`/`k` move down/up, `h`/`l` collapse/expand.
+    /// They mean nothing outside the menu.
+    menu_open: bool,
+    /// A launch prompt is up: every key answers it as `Confirm`, and a
+    /// click anywhere declines it.
+    confirm: bool,
+}
+
+/// Most times one action may repeat within a frame. Key auto-repeat
+/// events that pile up during a slow frame beyond this are dropped, so a
+/// held arrow stops close to where it is released.
+const MAX_REPEATS_PER_FRAME: usize = 3;
+
+/// Drain pending console input into the actions it produced, in order.
+/// Reading stops after an action that may change the mode, leaving later
+/// events queued to be read next frame under the new one.
+fn poll_input(
+    stdin_handle: HANDLE,
+    stdout_handle: HANDLE,
+    mode: InputMode,
+    keys: &KeyMap,
+) -> Vec<InputAction> {
+    let InputMode {
+        text_entry,
+        screensaver,
+        menu_open,
+        confirm,
+    } = mode;
+    let mut actions: Vec<InputAction> = Vec::new();
+    let mut repeats = 0;
+
+    while !actions.last().is_some_and(InputAction::changes_mode) {
         let mut count: u32 = 0;
         unsafe {
             GetNumberOfConsoleInputEvents(stdin_handle, &mut count);
@@ -477,701 +222,582 @@ fn poll_input(stdin_handle: HANDLE, stdout_handle: HANDLE) -> InputAction {
             break;
         }
 
-        match record.EventType as u32 {
+        let action = match record.EventType as u32 {
             KEY_EVENT => {
                 let key = unsafe { record.Event.KeyEvent };
                 if key.bKeyDown == 0 {
                     continue;
                 }
+                if screensaver {
+                    return vec![InputAction::Quit];
+                }
                 let vk = key.wVirtualKeyCode;
                 let ch = unsafe { key.uChar.UnicodeChar };
+                // Without ENABLE_PROCESSED_INPUT, Ctrl+C arrives as a key
+                if ch == 0x03 {
+                    SHUTDOWN.store(true, Ordering::Relaxed);
+                    break;
+                }
+                if confirm {
+                    actions.push(InputAction::Confirm(ch == b'y' as u16 || ch == b'Y' as u16));
+                    continue;
+                }
 
-                match vk {
-                    VK_ESCAPE => action = InputAction::Escape,
-                    VK_RETURN => action = InputAction::Enter,
-                    VK_TAB => action = InputAction::Tab,
-                    VK_UP => action = InputAction::Up,
-                    VK_DOWN => action = InputAction::Down,
-                    VK_LEFT => action = InputAction::Left,
-                    VK_RIGHT => action = InputAction::Right,
-                    _ => {
-                        if ch == b'q' as u16 || ch == b'Q' as u16 {
-                            action = InputAction::Quit;
+                let bound = if text_entry { None } else { keys.action(vk, ch, menu_open) };
+                if let Some(action) = bound {
+                    action
+                } else {
+                    match vk {
+                        VK_ESCAPE => InputAction::Escape,
+                        VK_RETURN => InputAction::Enter,
+                        VK_UP => InputAction::Up,
+                        VK_DOWN => InputAction::Down,
+                        VK_LEFT => InputAction::Left,
+                        VK_RIGHT => InputAction::Right,
+                        VK_HOME => InputAction::Home,
+                        VK_END => InputAction::End,
+                        VK_PRIOR => InputAction::PageUp,
+                        VK_NEXT => InputAction::PageDown,
+                        VK_BACK if text_entry => InputAction::Backspace,
+                        _ if text_entry => {
+                            if !(0x20..0x7F).contains(&ch) {
+                                continue;
+                            }
+                            InputAction::Char(ch as u8 as char)
+                        }
+                        _ => {
+                            if ch == b'R' as u16 {
+                                InputAction::Restart
+                            } else if ch == b'r' as u16 {
+                                InputAction::Reset
+                            } else if ch == b'/' as u16 {
+                                InputAction::Search
+                            } else if ch == b'f' as u16 {
+                                InputAction::ToggleFps
+                            } else if ch == b'c' as u16 {
+                                InputAction::CycleTheme
+                            } else if menu_open && ch == b'h' as u16 {
+                                InputAction::Left
+                            } else if menu_open && ch == b'j' as u16 {
+                                InputAction::Down
+                            } else if menu_open && ch == b'k' as u16 {
+                                InputAction::Up
+                            } else if menu_open && ch == b'l' as u16 {
+                                InputAction::Right
+                            } else {
+                                continue;
+                            }
                         }
                     }
                 }
             }
             WINDOW_BUFFER_SIZE_EVENT => {
-                let (w, h) = get_console_size(stdout_handle);
-                action = InputAction::Resize(w, h);
-            }
-            _ => {}
-        }
-    }
-
-    action
-}
-
-// ---------------------------------------------------------------------------
-// Application state
-// ---------------------------------------------------------------------------
-
-struct App {
-    drops: Vec<Drop>,
-    frame_count: u64,
-    menu_open: bool,
-    menu: Menu,
-    launch_message: Option<(String, Instant)>,
-    palette: AttrPalette,
-    cols: u16,
-    rows: u16,
-    rng: Rng,
-}
-
-impl App {
-    fn new(cols: u16, rows: u16) -> Self {
-        let mut rng = Rng::new();
-        let base = cols as usize;
-        let extra = base / 3;
-        let mut drops = Vec::with_capacity(base + extra);
-        for c in 0..cols {
-            drops.push(Drop::new(c, rows, &mut rng));
-        }
-        for _ in 0..extra {
-            let c = rng.gen_u32(cols as u32) as u16;
-            drops.push(Drop::new(c, rows, &mut rng));
-        }
-        Self {
-            drops,
-            frame_count: 0,
-            menu_open: false,
-            menu: Menu::load(),
-            launch_message: None,
-            palette: build_attr_palette(),
-            cols,
-            rows,
-            rng,
-        }
-    }
-
-    fn resize(&mut self, new_cols: u16, new_rows: u16) {
-        let old_cols = self.cols;
-        self.cols = new_cols;
-        self.rows = new_rows;
-
-        for drop in &mut self.drops {
-            if drop.col >= new_cols {
-                drop.col = self.rng.gen_u32(new_cols as u32) as u16;
-                drop.reset(new_rows, &mut self.rng);
-            }
-        }
-
-        if new_cols > old_cols {
-            let base = new_cols as usize;
-            let extra = base / 3;
-            let target = base + extra;
-            while self.drops.len() < target {
-                let c = self.rng.gen_u32(new_cols as u32) as u16;
-                self.drops.push(Drop::new(c, new_rows, &mut self.rng));
-            }
-        }
-
-        let base = new_cols as usize;
-        let extra = base / 3;
-        let target = base + extra;
-        if self.drops.len() > target {
-            self.drops.truncate(target);
-        }
-    }
-
-    fn update(&mut self) {
-        let rows = self.rows;
-        let rng = &mut self.rng;
-        for drop in &mut self.drops {
-            drop.update(rows, rng);
-        }
-        self.frame_count += 1;
-    }
-}
-
-// ---------------------------------------------------------------------------
-// Rendering into Cell buffer
-// ---------------------------------------------------------------------------
-
-fn render_to_buffer(buf: &mut [Cell], app: &App) {
-    let cols = app.cols as usize;
-    let rows = app.rows as usize;
-    let total = cols * rows;
-
-    for cell in buf[..total].iter_mut() {
-        *cell = Cell::BLANK;
-    }
-
-    let palette = &app.palette;
-
-    for drop in &app.drops {
-        let c = drop.col as usize;
-        if c >= cols || drop.len == 0 {
-            continue;
-        }
-
-        let head_row = drop.head;
-        let len = drop.len as i32;
-        let tail_row = head_row - len + 1;
-
-        let vis_top = tail_row.max(0);
-        let vis_bot = head_row.min(rows as i32 - 1);
-        if vis_top > vis_bot {
-            continue;
-        }
-
-        let i_start = (head_row - vis_bot) as u16;
-        let i_end = (head_row - vis_top) as u16;
-
-        for i in i_start..=i_end {
-            let r = (head_row - i as i32) as usize;
-            let ch = drop.trail_char_u16(i);
-
-            let attr = if i == 0 {
-                palette.head
-            } else if i <= 2 {
-                palette.near_head[(i - 1) as usize]
-            } else {
-                let max_trail = drop.max_len.saturating_sub(3).max(1) as usize;
-                let frac_idx = ((i as usize - 3) * TRAIL_PALETTE_SIZE) / max_trail;
-                let idx = frac_idx.min(TRAIL_PALETTE_SIZE - 1);
-                palette.trail[idx]
-            };
-
-            let cell = &mut buf[r * cols + c];
-            cell.ch = ch;
-            cell.attr = attr;
-        }
-    }
-
-    // Status bar
-    let status = if app.menu_open {
-        format!(" BADDERBLOOD // frame {} ", app.frame_count)
-    } else {
-        format!(
-            " BADDERBLOOD // frame {} // Tab for menu // q to quit ",
-            app.frame_count
-        )
-    };
-    let sw = status.len();
-    if cols > sw + 2 && rows > 1 {
-        let sx = cols - sw - 1;
-        let sy = rows - 1;
-        for (i, &b) in status.as_bytes().iter().enumerate() {
-            let cell = &mut buf[sy * cols + sx + i];
-            cell.ch = b as u16;
-            cell.attr = ATTR_STATUS;
-        }
-    }
-
-    // Launch message
-    if let Some((ref msg, when)) = app.launch_message {
-        if when.elapsed() < Duration::from_secs(3) {
-            let display = format!(" {} ", msg);
-            let mw = display.len();
-            if cols > mw + 2 && rows > 2 {
-                let mx = cols - mw - 1;
-                let my = rows - 2;
-                for (i, &b) in display.as_bytes().iter().enumerate() {
-                    let cell = &mut buf[my * cols + mx + i];
-                    cell.ch = b as u16;
-                    cell.attr = ATTR_MSG;
+                // Only the final size of a drag matters
+                if let Some(InputAction::Resize(..)) = actions.last() {
+                    actions.pop();
                 }
-            }
-        }
-    }
-
-    // Menu overlay
-    if app.menu_open {
-        render_menu_to_buffer(buf, &app.menu, cols, rows);
-    }
-}
-
-fn render_menu_to_buffer(buf: &mut [Cell], menu: &Menu, cols: usize, rows: usize) {
-    let menu_width = 80usize.min(cols.saturating_sub(4));
-    let menu_height = (rows * 2 / 3).max(16).min(rows.saturating_sub(4));
-    let mx = (cols.saturating_sub(menu_width)) / 2;
-    let my = (rows.saturating_sub(menu_height)) / 2;
-
-    let border_attr: u16 = 0x0A;
-    let title_attr: u16 = 0x0A;
-    let instr_attr: u16 = 0x02;
-    let cat_attr: u16 = 0x0A;
-    let cat_sel_attr: u16 = 0x20;
-    let entry_attr: u16 = 0x02;
-    let entry_sel_attr: u16 = 0x20;
-    let bg_attr: u16 = 0x00;
-
-    // Clear menu area
-    for r in my..my + menu_height {
-        for c in mx..mx + menu_width {
-            if r < rows && c < cols {
-                let cell = &mut buf[r * cols + c];
-                cell.ch = b' ' as u16;
-                cell.attr = bg_attr;
-            }
-        }
-    }
-
-    let draw_char = |buf: &mut [Cell], r: usize, c: usize, ch: u16, attr: u16| {
-        if r < rows && c < cols {
-            let cell = &mut buf[r * cols + c];
-            cell.ch = ch;
-            cell.attr = attr;
-        }
-    };
-
-    // Top/bottom borders
-    for c in mx..mx + menu_width {
-        draw_char(buf, my, c, b'-' as u16, border_attr);
-        draw_char(buf, my + menu_height - 1, c, b'-' as u16, border_attr);
-    }
-    for r in my..my + menu_height {
-        draw_char(buf, r, mx, b'|' as u16, border_attr);
-        draw_char(buf, r, mx + menu_width - 1, b'|' as u16, border_attr);
-    }
-    draw_char(buf, my, mx, b'+' as u16, border_attr);
-    draw_char(buf, my, mx + menu_width - 1, b'+' as u16, border_attr);
-    draw_char(buf, my + menu_height - 1, mx, b'+' as u16, border_attr);
-    draw_char(buf, my + menu_height - 1, mx + menu_width - 1, b'+' as u16, border_attr);
-
-    // Title
-    let title = " BadderBlood // Payload Launcher ";
-    let title_start = mx + 2;
-    for (i, &b) in title.as_bytes().iter().enumerate() {
-        if title_start + i < mx + menu_width - 1 {
-            draw_char(buf, my, title_start + i, b as u16, title_attr);
-        }
-    }
-
-    let inner_x = mx + 1;
-    let inner_y = my + 1;
-    let inner_w = menu_width - 2;
-    let inner_h = menu_height - 2;
-
-    let mut lines: Vec<(String, u16)> = Vec::new();
-
-    let instructions = " [Up/Dn] Navigate  [Enter] Select  [L/R] Collapse/Expand  [Esc] Close";
-    lines.push((instructions.to_string(), instr_attr));
-    lines.push((String::new(), bg_attr));
-
-    if menu.categories.is_empty() {
-        lines.push((" No payloads found in payload/ directory".to_string(), 0x04));
-    } else {
-        for (ci, cat) in menu.categories.iter().enumerate() {
-            let is_cat_selected = matches!(&menu.cursor, MenuIndex::Category(c) if *c == ci);
-            let prefix = if cat.expanded { "v " } else { "> " };
-            let attr = if is_cat_selected { cat_sel_attr } else { cat_attr };
-            lines.push((format!(" {}{}", prefix, cat.name), attr));
-
-            if cat.expanded {
-                for (ei, entry) in cat.entries.iter().enumerate() {
-                    let is_sel = matches!(&menu.cursor, MenuIndex::Entry(c, e) if *c == ci && *e == ei);
-                    let attr = if is_sel { entry_sel_attr } else { entry_attr };
-                    lines.push((format!("     {} ", entry.name), attr));
+                match get_console_size(stdout_handle) {
+                    Some((w, h)) => InputAction::Resize(w, h),
+                    None => continue,
                 }
             }
-        }
-    }
-
-    let visible_height = inner_h;
-    let mut scroll = menu.scroll_offset;
-    if lines.len() > visible_height {
-        let cursor_line = {
-            let mut line = 2usize;
-            for (ci, cat) in menu.categories.iter().enumerate() {
-                match &menu.cursor {
-                    MenuIndex::Category(c) if *c == ci => break,
-                    MenuIndex::Entry(c, e) if *c == ci => {
-                        line += 1 + *e;
-                        break;
-                    }
-                    _ => {}
+            MOUSE_EVENT if screensaver => return vec![InputAction::Quit],
+            MOUSE_EVENT => {
+                let mouse = unsafe { record.Event.MouseEvent };
+                // dwEventFlags == 0 is a button press/release, not a move
+                if mouse.dwEventFlags != 0
+                    || mouse.dwButtonState & FROM_LEFT_1ST_BUTTON_PRESSED == 0
+                {
+                    continue;
                 }
-                line += 1;
-                if cat.expanded {
-                    line += cat.entries.len();
+                if confirm {
+                    actions.push(InputAction::Confirm(false));
+                    continue;
                 }
+                let (left, top) = get_window_origin(stdout_handle);
+                let x = mouse.dwMousePosition.X - left;
+                let y = mouse.dwMousePosition.Y - top;
+                if x < 0 || y < 0 {
+                    continue;
+                }
+                InputAction::Click(x as u16, y as u16)
             }
-            line
+            _ => continue,
         };
-        if cursor_line < scroll + 2 {
-            scroll = cursor_line.saturating_sub(2);
-        } else if cursor_line >= scroll + visible_height - 2 {
-            scroll = cursor_line.saturating_sub(visible_height - 3);
-        }
-    } else {
-        scroll = 0;
-    }
 
-    for (li, (text, attr)) in lines.iter().enumerate().skip(scroll).take(visible_height) {
-        let row = inner_y + (li - scroll);
-        if row >= rows {
-            break;
-        }
-        for (ci, &b) in text.as_bytes().iter().enumerate() {
-            let col = inner_x + ci;
-            if col >= inner_x + inner_w {
-                break;
-            }
-            if col < cols {
-                draw_char(buf, row, col, b as u16, *attr);
+        // Typed filter text is never dropped, however fast it arrives
+        if actions.last() == Some(&action) && !matches!(action, InputAction::Char(_)) {
+            repeats += 1;
+            if repeats >= MAX_REPEATS_PER_FRAME {
+                continue;
             }
+        } else {
+            repeats = 0;
         }
+        actions.push(action);
     }
+
+    actions
 }
 
 // ---------------------------------------------------------------------------
-// VT-based diff renderer: only emit escape sequences for changed cells
+// Console control events (Ctrl+C, Ctrl+Break, window close)
 // ---------------------------------------------------------------------------
 
-/// VT output buffer. Uses WriteFile for maximum throughput.
-/// All characters are ASCII so no multi-byte encoding needed.
-struct VtRenderer {
-    out: Vec<u8>,
-}
-
-impl VtRenderer {
-    fn new(capacity: usize) -> Self {
-        Self {
-            out: Vec::with_capacity(capacity),
-        }
-    }
-
-    /// Compare `cur` against `prev`, emit VT sequences for differences,
-    /// then copy cur -> prev. Writes output via WriteFile.
-    fn render_diff(
-        &mut self,
-        cur: &[Cell],
-        prev: &mut [Cell],
-        cols: usize,
-        rows: usize,
-        handle: HANDLE,
-    ) {
-        self.out.clear();
-
-        let total = cols * rows;
-        let mut last_attr: u16 = 0xFFFF;
-        let mut cursor_row: usize = usize::MAX;
-        let mut cursor_col: usize = usize::MAX;
-
-        for idx in 0..total {
-            let c = cur[idx];
-            let p = prev[idx];
-            if c == p {
-                continue;
-            }
-            prev[idx] = c;
-
-            let r = idx / cols;
-            let col = idx % cols;
-
-            if r != cursor_row || col != cursor_col {
-                write_cursor_pos(&mut self.out, r + 1, col + 1);
-            }
-
-            if c.attr != last_attr {
-                self.out.extend_from_slice(attr_to_sgr(c.attr));
-                last_attr = c.attr;
-            }
-
-            self.out.push(c.ch as u8);
+/// Set by the control handler; the main loop exits through its normal
+/// cleanup (cursor, console modes, code page) when it sees it.
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
 
-            cursor_row = r;
-            cursor_col = col + 1;
+unsafe extern "system" fn on_console_ctrl(ctrl_type: u32) -> BOOL {
+    match ctrl_type {
+        CTRL_C_EVENT | CTRL_BREAK_EVENT => {
+            SHUTDOWN.store(true, Ordering::Relaxed);
+            1
         }
-
-        if !self.out.is_empty() {
-            self.out.extend_from_slice(b"\x1b[0m");
-
-            let mut written: u32 = 0;
-            unsafe {
-                WriteFile(
-                    handle,
-                    self.out.as_ptr(),
-                    self.out.len() as u32,
-                    &mut written,
-                    std::ptr::null_mut(),
-                );
-            }
-        }
-    }
-
-    /// Full repaint: mark entire prev buffer as dirty then render_diff.
-    fn render_full(
-        &mut self,
-        cur: &[Cell],
-        prev: &mut [Cell],
-        cols: usize,
-        rows: usize,
-        handle: HANDLE,
-    ) {
-        let sentinel = Cell { ch: 0xFFFF, attr: 0xFFFF };
-        for p in prev.iter_mut() {
-            *p = sentinel;
+        CTRL_CLOSE_EVENT => {
+            // The process is killed once this returns; give the main loop a
+            // moment to restore the console first.
+            SHUTDOWN.store(true, Ordering::Relaxed);
+            std::thread::sleep(Duration::from_millis(500));
+            1
         }
-        self.render_diff(cur, prev, cols, rows, handle);
-    }
-}
-
-#[inline]
-fn write_cursor_pos(buf: &mut Vec<u8>, row: usize, col: usize) {
-    buf.extend_from_slice(b"\x1b[");
-    write_usize(buf, row);
-    buf.push(b';');
-    write_usize(buf, col);
-    buf.push(b'H');
-}
-
-#[inline]
-fn write_usize(buf: &mut Vec<u8>, n: usize) {
-    if n < 10 {
-        buf.push(b'0' + n as u8);
-    } else if n < 100 {
-        buf.push(b'0' + (n / 10) as u8);
-        buf.push(b'0' + (n % 10) as u8);
-    } else if n < 1000 {
-        buf.push(b'0' + (n / 100) as u8);
-        buf.push(b'0' + ((n / 10) % 10) as u8);
-        buf.push(b'0' + (n % 10) as u8);
-    } else {
-        let s = n.to_string();
-        buf.extend_from_slice(s.as_bytes());
+        _ => 0,
     }
 }
 
 // ---------------------------------------------------------------------------
-// FPS tracking
+// Console restore, on exit and on panic
 // ---------------------------------------------------------------------------
 
-struct FpsTracker {
-    window_frames: u64,
-    total_frames: u64,
-    window_start: Instant,
-    fps_file_path: PathBuf,
-}
+/// Show the cursor, reset colours and clear away the rain.
+const CLEANUP_SEQUENCE: &[u8] = b"\x1b[0m\x1b[?25h\x1b[2J\x1b[H";
 
-impl FpsTracker {
-    fn new() -> Self {
-        let fps_file_path = std::env::current_exe()
-            .ok()
-            .and_then(|p| p.parent().map(|d| d.join("fps.txt")))
-            .unwrap_or_else(|| PathBuf::from("fps.txt"));
-
-        Self {
-            window_frames: 0,
-            total_frames: 0,
-            window_start: Instant::now(),
-            fps_file_path,
-        }
-    }
+/// Console modes and output code page from before startup changed them.
+#[derive(Clone, Copy)]
+struct SavedConsole {
+    stdin_mode: u32,
+    stdout_mode: u32,
+    output_cp: u32,
+}
 
-    fn tick(&mut self) {
-        self.window_frames += 1;
-        self.total_frames += 1;
+/// Set once the console is reconfigured, for the panic hook.
+static SAVED_CONSOLE: OnceLock<SavedConsole> = OnceLock::new();
 
-        let elapsed = self.window_start.elapsed();
-        if elapsed >= Duration::from_secs(10) {
-            let fps = self.window_frames as f64 / elapsed.as_secs_f64();
-            self.log_fps(fps);
-            self.window_frames = 0;
-            self.window_start = Instant::now();
+impl SavedConsole {
+    fn restore(self) {
+        unsafe {
+            check_win32(
+                "restore SetConsoleMode(stdin)",
+                SetConsoleMode(GetStdHandle(STD_INPUT_HANDLE), self.stdin_mode),
+            );
+            check_win32(
+                "restore SetConsoleMode(stdout)",
+                SetConsoleMode(GetStdHandle(STD_OUTPUT_HANDLE), self.stdout_mode),
+            );
+            check_win32("restore SetConsoleOutputCP", SetConsoleOutputCP(self.output_cp));
         }
     }
+}
 
-    fn log_fps(&self, fps: f64) {
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
-        let line = format!(
-            "timestamp={} fps={:.1} total_frames={}\n",
-            timestamp, fps, self.total_frames
-        );
-        if let Ok(mut f) = std::fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&self.fps_file_path)
-        {
-            let _ = f.write_all(line.as_bytes());
+/// Put the console back before the default hook prints a panic, so a crash
+/// mid-frame doesn't leave a hidden cursor and raw-mode input behind.
+fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if let Some(saved) = SAVED_CONSOLE.get() {
+            let stdout_handle = unsafe { GetStdHandle(STD_OUTPUT_HANDLE) };
+            let _ = write_console(stdout_handle, CLEANUP_SEQUENCE, CLEANUP_SEQUENCE.len());
+            saved.restore();
         }
-    }
+        default_hook(info);
+    }));
 }
 
 // ---------------------------------------------------------------------------
 // Main loop
 // ---------------------------------------------------------------------------
 
+/// Most `app.update` calls one frame runs with `--catch-up`; anything
+/// further behind is dropped instead of fast-forwarding the rain.
+const MAX_CATCH_UP_UPDATES: u32 = 3;
+
 fn main() -> io::Result<()> {
-    unsafe { timeBeginPeriod(1) };
+    let config = match Config::from_args() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("badderblood: {}", e);
+            std::process::exit(2);
+        }
+    };
+    VERBOSE.store(config.verbose, Ordering::Relaxed);
+    install_panic_hook();
+
+    // --list: report what the scanner finds, leaving the console untouched
+    if config.list {
+        return Menu::load().write_tree(&mut io::stdout().lock());
+    }
+
+    // A missing or unusable glyph file is not fatal: keep the built-in set
+    // so the rain still runs, and warn once the console is restored.
+    let mut glyph_warning = None;
+    let glyphs = match &config.glyphs {
+        Some(path) => GlyphSet::load(path).unwrap_or_else(|e| {
+            glyph_warning = Some(format!(
+                "badderblood: warning: glyphs {}: {}; using built-in set",
+                path.display(),
+                e
+            ));
+            GlyphSet::preset(config.glyph_preset)
+        }),
+        None => GlyphSet::preset(config.glyph_preset),
+    };
+
+    // Opened before touching the console so a bad path fails cleanly
+    let record = match &config.record {
+        Some(path) => match File::create(path) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                eprintln!("badderblood: record {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
 
     let stdin_handle: HANDLE = unsafe { GetStdHandle(STD_INPUT_HANDLE) };
     let stdout_handle: HANDLE = unsafe { GetStdHandle(STD_OUTPUT_HANDLE) };
 
+    // Redirected to a file or pipe there is nothing to draw on, and the
+    // escape codes would only garble whatever reads them
+    let mut probe_mode: u32 = 0;
+    if unsafe { GetConsoleMode(stdout_handle, &mut probe_mode) } == 0 {
+        eprintln!("badderblood: output is not a console (is stdout redirected?)");
+        std::process::exit(1);
+    }
+
+    unsafe {
+        timeBeginPeriod(1);
+        check_win32(
+            "SetConsoleCtrlHandler",
+            SetConsoleCtrlHandler(Some(on_console_ctrl), 1),
+        );
+    }
+
     // Save original console modes
     let mut original_stdin_mode: u32 = 0;
     let mut original_stdout_mode: u32 = 0;
+    let original_output_cp = unsafe { GetConsoleOutputCP() };
     unsafe {
-        GetConsoleMode(stdin_handle, &mut original_stdin_mode);
-        GetConsoleMode(stdout_handle, &mut original_stdout_mode);
+        check_win32(
+            "GetConsoleMode(stdin)",
+            GetConsoleMode(stdin_handle, &mut original_stdin_mode),
+        );
+        check_win32(
+            "GetConsoleMode(stdout)",
+            GetConsoleMode(stdout_handle, &mut original_stdout_mode),
+        );
 
-        // Enable VT processing on stdout so we can use ANSI escape sequences
-        SetConsoleMode(
+        // Enable VT processing on stdout so we can use ANSI escape sequences.
+        // Consoles older than Windows 10 1511 reject the flag (or drop it),
+        // so read the mode back; without VT every frame would print as raw
+        // escape codes.
+        let mut vt_mode: u32 = 0;
+        let vt_enabled = SetConsoleMode(
             stdout_handle,
             original_stdout_mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING,
-        );
+        ) != 0
+            && GetConsoleMode(stdout_handle, &mut vt_mode) != 0
+            && vt_mode & ENABLE_VIRTUAL_TERMINAL_PROCESSING != 0;
+        if !vt_enabled {
+            log_failure("SetConsoleMode(stdout)", &io::Error::last_os_error());
+            timeEndPeriod(1);
+            eprintln!(
+                "badderblood: this console does not support VT escape sequences \
+                 (Windows 10 1511 or later is required)"
+            );
+            std::process::exit(1);
+        }
+
+        // UTF-8 output so non-ASCII glyphs survive WriteFile
+        check_win32("SetConsoleOutputCP", SetConsoleOutputCP(CP_UTF8));
 
-        // Enable window resize events on stdin
-        SetConsoleMode(stdin_handle, ENABLE_EXTENDED_FLAGS | ENABLE_WINDOW_INPUT);
+        // Enable window resize and mouse events on stdin (ENABLE_EXTENDED_FLAGS
+        // without quick-edit lets clicks reach us instead of selecting text)
+        check_win32(
+            "SetConsoleMode(stdin)",
+            SetConsoleMode(
+                stdin_handle,
+                ENABLE_EXTENDED_FLAGS | ENABLE_WINDOW_INPUT | ENABLE_MOUSE_INPUT,
+            ),
+        );
     }
+    let saved_console = SavedConsole {
+        stdin_mode: original_stdin_mode,
+        stdout_mode: original_stdout_mode,
+        output_cp: original_output_cp,
+    };
+    let _ = SAVED_CONSOLE.set(saved_console);
 
     // Hide cursor and clear screen
-    {
-        let init = b"\x1b[?25l\x1b[2J\x1b[H";
-        let mut written: u32 = 0;
-        unsafe {
-            WriteFile(stdout_handle, init.as_ptr(), init.len() as u32, &mut written, std::ptr::null_mut());
-        }
-    }
+    let mut out = ConsoleOut {
+        handle: stdout_handle,
+        chunk: config.write_chunk,
+        record,
+    };
+    out.write(b"\x1b[?25l\x1b[2J\x1b[H");
 
-    let (cols, rows) = get_console_size(stdout_handle);
-    let mut app = App::new(cols, rows);
+    let original_title = get_console_title();
+    out.write(&title_sequence(&config.title));
+
+    let measured = config.size.or_else(|| get_console_size(stdout_handle));
+    let (cols, rows) = measured.unwrap_or(FALLBACK_SIZE);
+    let mut dirty = DirtyCells::new(config.dirty_tracking);
+    let mut app = App::new(cols, rows, config, glyphs);
 
     let total_cells = cols as usize * rows as usize;
     let mut cur_buf: Vec<Cell> = vec![Cell::BLANK; total_cells];
-    let mut prev_buf: Vec<Cell> = vec![Cell { ch: 0xFFFF, attr: 0xFFFF }; total_cells];
+    let mut prev_buf: Vec<Cell> = vec![Cell::STALE; total_cells];
 
     // Pre-allocate VT output buffer (generous: ~10 bytes per changed cell)
-    let mut vt = VtRenderer::new(total_cells * 10);
+    let mut vt = VtRenderer::new(total_cells * 10, app.config.transparent);
 
-    let mut fps_tracker = FpsTracker::new();
+    let target_fps = app.config.fps;
+    let mut fps_tracker = FpsTracker::new(target_fps, app.config.profile, app.config.fps_format);
 
-    let target_fps: u64 = 30;
-    let frame_dur = Duration::from_micros(1_000_000 / target_fps);
+    // Frame pacing uses thread::sleep, which even with timeBeginPeriod(1)
+    // only wakes at ~1ms granularity and may oversleep. Sleeping until an
+    // absolute deadline that advances by exactly `frame_dur` lets the next
+    // frame absorb each overshoot, so the long-run rate stays on target.
+    let frame_dur = Duration::from_micros(1_000_000 / target_fps as u64);
+    let mut next_frame = Instant::now() + frame_dur;
 
     let mut force_full_repaint = true;
+    // --catch-up: time past the frame budget not yet made up with updates
+    let mut lag = Duration::ZERO;
 
-    loop {
-        let start = Instant::now();
+    // --bench: fixed frame count, no input and no frame pacing
+    let bench = app.config.bench;
+    let bench_start = Instant::now();
+    let mut bench_frames: u64 = 0;
 
-        match poll_input(stdin_handle, stdout_handle) {
-            InputAction::Quit => {
-                if !app.menu_open {
-                    break;
-                }
+    'frames: loop {
+        if SHUTDOWN.load(Ordering::Relaxed) {
+            break;
+        }
+
+        let actions = if let Some(frames) = bench {
+            if bench_frames == frames {
+                break;
             }
-            InputAction::Escape => {
-                if app.menu_open {
-                    app.menu_open = false;
-                } else {
-                    break;
+            bench_frames += 1;
+            Vec::new()
+        } else {
+            let mode = InputMode {
+                text_entry: app.menu_open && app.menu.filtering,
+                screensaver: app.config.screensaver,
+                menu_open: app.menu_open,
+                confirm: app.pending_launch.is_some(),
+            };
+            poll_input(stdin_handle, stdout_handle, mode, &app.config.keys)
+        };
+        if !actions.is_empty() {
+            app.last_input = Instant::now();
+        }
+        for action in actions {
+            match action {
+                InputAction::Quit if !app.menu_open => break 'frames,
+                InputAction::Quit if app.config.q_closes_menu && !app.config.menu_first => {
+                    app.menu_open = false
                 }
-            }
-            InputAction::Tab | InputAction::Enter if !app.menu_open => {
-                app.menu_open = true;
-            }
-            InputAction::Enter if app.menu_open => {
-                match &app.menu.cursor {
-                    MenuIndex::Category(ci) => {
-                        let ci = *ci;
-                        app.menu.categories[ci].expanded = !app.menu.categories[ci].expanded;
-                    }
-                    MenuIndex::Entry(ci, ei) => {
-                        let path = app.menu.categories[*ci].entries[*ei].path.clone();
-                        let display = app.menu.categories[*ci].entries[*ei].name.clone();
-                        launch_ps1(&path);
-                        app.launch_message =
-                            Some((format!("Launched: {}", display), Instant::now()));
+                InputAction::Escape => {
+                    if app.menu_open && (app.menu.filtering || !app.menu.filter.is_empty()) {
+                        app.menu.clear_filter();
+                    } else if app.menu_open && !app.config.menu_first {
                         app.menu_open = false;
+                    } else {
+                        break 'frames;
                     }
                 }
-            }
-            InputAction::Up if app.menu_open => app.menu.move_up(),
-            InputAction::Down if app.menu_open => app.menu.move_down(),
-            InputAction::Left if app.menu_open => {
-                match &app.menu.cursor {
-                    MenuIndex::Entry(ci, _) => {
-                        let ci = *ci;
-                        app.menu.categories[ci].expanded = false;
-                        app.menu.cursor = MenuIndex::Category(ci);
-                    }
-                    MenuIndex::Category(ci) => {
-                        app.menu.categories[*ci].expanded = false;
-                    }
+                InputAction::Tab => app.open_menu(),
+                // The menu is not interactive until its intro animation finishes
+                InputAction::Enter
+                | InputAction::Click(..)
+                | InputAction::Up
+                | InputAction::Down
+                | InputAction::Left
+                | InputAction::Right
+                | InputAction::Home
+                | InputAction::End
+                | InputAction::PageUp
+                | InputAction::PageDown
+                    if app.menu_intro_progress().is_some() => {}
+                InputAction::Enter if app.menu_open && app.menu.filtering => {
+                    app.menu.filtering = false;
                 }
-            }
-            InputAction::Right if app.menu_open => {
-                if let MenuIndex::Category(ci) = &app.menu.cursor {
-                    app.menu.categories[*ci].expanded = true;
+                // Nothing to act on when the filter hides every row
+                InputAction::Enter | InputAction::Left | InputAction::Right
+                    if app.menu_open && !app.menu.cursor_visible() => {}
+                InputAction::Enter if app.menu_open => app.activate_selection(),
+                InputAction::Click(x, y) if app.menu_open => app.click(x, y),
+                InputAction::Up if app.menu_open => app.menu.move_up(),
+                InputAction::Down if app.menu_open => app.menu.move_down(),
+                InputAction::Home if app.menu_open => app.menu.move_home(),
+                InputAction::End if app.menu_open => app.menu.move_end(),
+                InputAction::PageUp if app.menu_open => app.menu.page_up(app.menu_page_rows()),
+                InputAction::PageDown if app.menu_open => app.menu.page_down(app.menu_page_rows()),
+                InputAction::Left if app.menu_open => app.menu.collapse_selected(),
+                InputAction::Right if app.menu_open => app.menu.expand_selected(),
+                InputAction::Restart => {
+                    app.restart();
+                    force_full_repaint = true;
                 }
-            }
-            InputAction::Resize(w, h) => {
-                app.resize(w, h);
-
-                let new_total = w as usize * h as usize;
-                cur_buf.resize(new_total, Cell::BLANK);
-                prev_buf.resize(new_total, Cell { ch: 0xFFFF, attr: 0xFFFF });
-
-                // Clear screen on resize
-                {
-                    let clear = b"\x1b[2J\x1b[H";
-                    let mut written: u32 = 0;
-                    unsafe {
-                        WriteFile(stdout_handle, clear.as_ptr(), clear.len() as u32, &mut written, std::ptr::null_mut());
+                InputAction::Reset => app.reset_drops(),
+                InputAction::Confirm(yes) => {
+                    app.resolve_pending_launch(yes);
+                    // Output goes to payload.log, but the payload still
+                    // shares our console and may show the cursor or change
+                    // colours; repaint and re-hide the cursor
+                    force_full_repaint |= yes;
+                }
+                InputAction::Pause => app.paused = !app.paused,
+                InputAction::ToggleFps => app.show_fps = !app.show_fps,
+                InputAction::CycleTheme => {
+                    app.cycle_theme();
+                    force_full_repaint = true;
+                }
+                InputAction::Search if app.menu_open => app.menu.filtering = true,
+                InputAction::Char(ch) => app.menu.push_filter(ch),
+                InputAction::Backspace => app.menu.pop_filter(),
+                InputAction::Resize(..) if app.config.size.is_some() => {}
+                InputAction::Resize(w, h) => {
+                    // Growing taller at the same width leaves every existing
+                    // row in place, so prev_buf stays valid for them and the
+                    // new rows (unknown in prev_buf) get painted by the diff.
+                    let grew_down = w == app.cols && h >= app.rows;
+                    app.resize(w, h);
+
+                    let new_total = w as usize * h as usize;
+                    cur_buf.resize(new_total, Cell::BLANK);
+                    prev_buf.resize(new_total, Cell::STALE);
+                    dirty.invalidate();
+
+                    // Anything else reflows the grid: clear and repaint
+                    if !grew_down {
+                        out.write(b"\x1b[2J\x1b[H");
+                        force_full_repaint = true;
                     }
                 }
-                force_full_repaint = true;
+                _ => {}
             }
-            _ => {}
         }
 
-        app.update();
-
-        render_to_buffer(&mut cur_buf, &app);
-
-        if force_full_repaint {
-            vt.render_full(
-                &cur_buf,
-                &mut prev_buf,
-                app.cols as usize,
-                app.rows as usize,
-                stdout_handle,
-            );
-            force_full_repaint = false;
-        } else {
-            vt.render_diff(
-                &cur_buf,
-                &mut prev_buf,
-                app.cols as usize,
-                app.rows as usize,
-                stdout_handle,
-            );
+        if let Some(interval) = app.config.restart_interval {
+            if app.last_restart.elapsed() >= interval {
+                app.restart();
+                force_full_repaint = true;
+            }
         }
 
-        fps_tracker.tick();
+        app.reap_payloads();
 
-        let elapsed = start.elapsed();
-        if elapsed < frame_dur {
-            std::thread::sleep(frame_dur - elapsed);
+        // The rain is a frozen backdrop while browsing the menu, so the
+        // diff renderer has nothing to emit for it.
+        let mut phases = PhaseTimes::default();
+        let phase_start = Instant::now();
+        if !app.paused && (!app.menu_open || app.config.animate_under_menu) {
+            let behind = (lag.as_micros() / frame_dur.as_micros()) as u32;
+            let updates = 1 + behind.min(MAX_CATCH_UP_UPDATES - 1);
+            lag = lag.saturating_sub(frame_dur * (updates - 1));
+            if lag >= frame_dur {
+                lag = Duration::ZERO;
+            }
+            for _ in 0..updates {
+                app.update();
+            }
+        } else {
+            lag = Duration::ZERO;
+        }
+        phases.update = phase_start.elapsed();
+
+        // A window dragged down to nothing has no frame to draw; keep the
+        // pending full repaint for when it comes back.
+        if app.cols > 0 && app.rows > 0 {
+            let phase_start = Instant::now();
+            render_to_buffer(&mut cur_buf, &app, &mut dirty);
+            phases.render = phase_start.elapsed();
+
+            let phase_start = Instant::now();
+            let (cols, rows) = (app.cols as usize, app.rows as usize);
+            let frame = if force_full_repaint || app.config.full_repaint {
+                force_full_repaint = false;
+                vt.render_full(&cur_buf, &mut prev_buf, cols, rows)
+            } else {
+                vt.render_diff(&cur_buf, &mut prev_buf, cols, rows, dirty.candidates())
+            };
+            if !frame.is_empty() {
+                out.write(frame);
+            }
+            phases.diff = phase_start.elapsed();
+        }
+
+        fps_tracker.tick(phases);
+        app.current_fps = fps_tracker.current_fps();
+        app.clock = Some(local_clock());
+
+        if bench.is_none() {
+            let now = Instant::now();
+            if now < next_frame {
+                std::thread::sleep(next_frame - now);
+            } else if now - next_frame >= frame_dur {
+                // A whole frame late (a stalled launch, a window drag): start
+                // a fresh schedule instead of racing through the backlog
+                if app.config.catch_up {
+                    lag += now - next_frame;
+                }
+                next_frame = now;
+            }
+            next_frame += frame_dur;
         }
     }
+    let bench_elapsed = bench_start.elapsed();
+
+    app.menu.save_state();
 
     // Cleanup: show cursor, reset colors, restore console modes and codepage
     {
-        let cleanup = b"\x1b[0m\x1b[?25h\x1b[2J\x1b[H";
-        let mut written: u32 = 0;
+        out.write(CLEANUP_SEQUENCE);
+        if original_title.is_empty() {
+            out.write(&title_sequence(""));
+        } else {
+            let mut title = original_title;
+            title.push(0);
+            check_win32("restore SetConsoleTitleW", unsafe { SetConsoleTitleW(title.as_ptr()) });
+        }
+        saved_console.restore();
         unsafe {
-            WriteFile(stdout_handle, cleanup.as_ptr(), cleanup.len() as u32, &mut written, std::ptr::null_mut());
-            SetConsoleMode(stdin_handle, original_stdin_mode);
-            SetConsoleMode(stdout_handle, original_stdout_mode);
             timeEndPeriod(1);
         }
     }
 
+    // Reported after cleanup so the screen clear doesn't wipe it
+    if let Some(warning) = glyph_warning {
+        eprintln!("{}", warning);
+    }
+    if measured.is_none() {
+        eprintln!(
+            "badderblood: warning: the console did not report its size; assumed {}x{}",
+            FALLBACK_SIZE.0, FALLBACK_SIZE.1
+        );
+    }
+    if bench.is_some() {
+        let frames = bench_frames.max(1);
+        eprintln!(
+            "badderblood: bench frames={} total={:.3}s avg_frame={:.3}ms",
+            bench_frames,
+            bench_elapsed.as_secs_f64(),
+            bench_elapsed.as_secs_f64() * 1000.0 / frames as f64
+        );
+    }
+
     Ok(())
 }